@@ -8,6 +8,9 @@ CONTAINER="${NAME}:${TAG}"
 # Host directory for the /app/data Volume
 DATA_DIR=./data-run
 
+# File of the secret key of the session cookies, kept between the runs
+SECRET_KEY_FILE=./secret-key
+
 
 # Build the container
 podman build -t "${CONTAINER}" .
@@ -16,5 +19,9 @@ if [ $? -eq 0 ]
 then
 	# Run the container
 	mkdir -p "${DATA_DIR}"
-	podman run -d --name ${NAME} -v "${DATA_DIR}:/app/data" -p 8000:8000 "${CONTAINER}"
+	if [ ! -f "${SECRET_KEY_FILE}" ]
+	then
+		( umask 077 && openssl rand -base64 32 > "${SECRET_KEY_FILE}" )
+	fi
+	podman run -d --name ${NAME} -v "${DATA_DIR}:/app/data" -p 8000:8000 -e ROCKET_SECRET_KEY="$(cat "${SECRET_KEY_FILE}")" "${CONTAINER}"
 fi
@@ -0,0 +1,188 @@
+//! The administration tasks, run from the command line without starting the server.
+//!
+//! They operate on the storage backend of a board: exporting its posts to a
+//! file, importing posts from a file (for example one exported from another
+//! backend), pruning the oldest posts, and checking a configuration.
+
+use crate::config::Config;
+use crate::core::{History, Post};
+use crate::core::timezone::Timezone;
+use crate::storage::StorageBackend;
+use rocket::serde::json::serde_json;
+use std::collections::HashSet;
+use std::io;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+
+/// The formats the posts are exported and imported in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+	/// The lines of the CSV storage, without header
+	Csv,
+	/// An array of JSON objects
+	Json,
+}
+
+/// Parses `csv` or `json`.
+impl FromStr for DumpFormat {
+	type Err = String;
+
+	fn from_str(p_text: &str) -> Result<DumpFormat, String> {
+		match p_text.to_lowercase().as_str() {
+			"csv"  => Ok(DumpFormat::Csv),
+			"json" => Ok(DumpFormat::Json),
+			_      => Err(format!("Invalid format '{}': expected 'csv' or 'json'", p_text))
+		}
+	}
+}
+
+
+/// Writes all the stored posts of a board, in id order.
+///
+/// Returns the number of exported posts.
+pub fn export_posts<S, W>(p_storage: &S, p_format: DumpFormat, p_output: W) -> io::Result<usize>
+		where S: StorageBackend + ?Sized, W: Write {
+	let posts = p_storage.posts_between(1, u64::MAX)?;
+	match p_format {
+		DumpFormat::Csv => {
+			let mut writer = csv::WriterBuilder::new()
+					.has_headers(false)
+					.from_writer(p_output);
+			for post in &posts {
+				writer.serialize(post)?;
+			}
+			writer.flush()?;
+		},
+		DumpFormat::Json => {
+			let mut output = p_output;
+			serde_json::to_writer_pretty(&mut output, &posts)?;
+			writeln!(output)?;
+		}
+	}
+	Ok(posts.len())
+}
+
+
+/// Reads posts exported by `export_posts()` and saves them in a storage.
+///
+/// The posts keep their id, so only the ones after the last id given by the
+/// storage are imported, in id order; the others are skipped.
+///
+/// Returns the numbers of imported and skipped posts.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::admin::{ export_posts, import_posts, DumpFormat };
+/// use oxyboard::core::{ History, UserPost };
+/// use oxyboard::storage::{ CsvFileStorage, StorageBackend };
+///
+/// let dir = std::env::temp_dir().join(format!("oxyboard-admin-{}", std::process::id()));
+/// let source = CsvFileStorage::new(&dir.join("source").to_string_lossy().to_string(), String::from("history.csv"));
+/// let target = CsvFileStorage::new(&dir.join("target").to_string_lossy().to_string(), String::from("history.csv"));
+///
+/// let mut history = History::new(&String::from("Oxyboard"), 16);
+/// for message in &["Plop!", "Pika!"] {
+///     history.add_post(UserPost::new(String::new(), String::from("Firefox/48.0.1"), message.to_string()));
+/// }
+/// for post in history.iter() {
+///     source.save_post(post).unwrap();
+/// }
+///
+/// let mut dump = Vec::new();
+/// assert_eq!(export_posts(&source, DumpFormat::Json, &mut dump).unwrap(), 2);
+/// assert_eq!(import_posts(&target, DumpFormat::Json, &dump[..]).unwrap(), (2, 0));
+/// assert_eq!(import_posts(&target, DumpFormat::Json, &dump[..]).unwrap(), (0, 2));
+/// assert_eq!(target.posts_between(1, 2).unwrap()[1].message(), "Pika!");
+///
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn import_posts<S, R>(p_storage: &S, p_format: DumpFormat, p_input: R) -> io::Result<(usize, usize)>
+		where S: StorageBackend + ?Sized, R: Read {
+	let mut posts: Vec<Post> = match p_format {
+		DumpFormat::Csv  => csv::ReaderBuilder::new()
+				.has_headers(false)
+				.flexible(true)
+				.from_reader(p_input)
+				.deserialize()
+				.collect::<Result<_, _>>()?,
+		DumpFormat::Json => serde_json::from_reader(p_input)?
+	};
+	let count = posts.len();
+	posts.sort_by_key(Post::id);
+	posts.dedup_by_key(|p| p.id());
+
+	// The ids already given, deleted posts included, are never given again
+	let mut history = History::new(&String::from("import"), 1);
+	p_storage.load_history(&mut history)?;
+	let state = p_storage.load_state()?;
+	if let Some(state) = &state {
+		history.skip_post_ids(state.next_post_id.saturating_sub(1));
+	}
+	let next_id = history.next_post_id();
+
+	let mut imported = 0;
+	let mut last_id = None;
+	for post in posts.iter().filter(|p| p.id() >= next_id) {
+		p_storage.save_post(post)?;
+		imported += 1;
+		last_id = Some(post.id());
+	}
+
+	// The sequence of the board goes on after the imported posts
+	if let (Some(mut state), Some(id)) = (state, last_id) {
+		state.next_post_id = id + 1;
+		p_storage.save_state(&state)?;
+	}
+	Ok((imported, count - imported))
+}
+
+
+/// Checks a configuration, giving the description of its problems.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::admin::check_config;
+/// use oxyboard::config;
+///
+/// let mut cfg = config::default();
+/// assert!(check_config(&cfg).is_empty());
+///
+/// cfg.board.timezone = String::from("Mars/Olympus");
+/// cfg.board.history_size = 0;
+/// cfg.limits.ip.per_minute = 0;
+/// cfg.storage.segment_size = 0;
+/// assert_eq!(check_config(&cfg).len(), 4);
+/// ```
+pub fn check_config(p_config: &Config) -> Vec<String> {
+	let mut problems = Vec::new();
+	let mut ids = HashSet::new();
+
+	for board in std::iter::once(&p_config.board).chain(p_config.boards.iter()) {
+		if !ids.insert(board.id.as_str()) {
+			problems.push(format!("Board '{}' is declared more than once", board.id));
+		}
+		if board.id.is_empty() || board.id.contains(['/', '\\', '.']) {
+			problems.push(format!("Board '{}' has an invalid id", board.id));
+		}
+		if board.history_size == 0 {
+			problems.push(format!("Board '{}' has an empty history", board.id));
+		}
+		if let Err(e) = board.timezone.parse::<Timezone>() {
+			problems.push(format!("Board '{}': {}", board.id, e));
+		}
+	}
+	let limits = [("IP", &p_config.limits.ip), ("login", &p_config.limits.login), ("User-Agent", &p_config.limits.user_agent), ("account", &p_config.limits.account)];
+	for (kind, rate) in limits.iter().filter(|(_, r)| r.burst > 0 && r.per_minute == 0) {
+		problems.push(format!("The {} limit with a burst of {} is never refilled: its per_minute must not be 0", kind, rate.burst));
+	}
+	if p_config.storage.data_dir.is_empty() {
+		problems.push(String::from("The storage has no data directory"));
+	}
+	if p_config.storage.segment_size == 0 {
+		problems.push(String::from("The storage has empty segments: its segment_size must not be 0"));
+	}
+	problems
+}
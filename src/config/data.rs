@@ -1,64 +1,360 @@
 /// This the root of the configuration structure.
 ///
+/// The `board` section describes the default board, the one served by the
+/// unprefixed routes (`/board`, `/backend`, `/post`...). Additional boards
+/// can be declared with a `[[boards]]` array; they are served under
+/// `/b/<id>/`.
+///
 /// # Examples
 ///
 /// ```
-/// use oxyboard::config::{ Config, BoardParams, StorageParams };
+/// use oxyboard::config::{ Config, BoardParams, FsyncPolicy, LimitsParams, ModerationParams, RotationPolicy, StorageParams, StorageKind };
 ///
 /// let cfg = Config {
 ///         board: BoardParams {
-///             name         : String::from("oxyboard"),
-///             history_size : 512,
+///             id                 : String::from("default"),
+///             name               : String::from("oxyboard"),
+///             history_size       : 512,
+///             timezone           : String::from("local"),
+///             max_message_length : 512,
+///             refresh_interval   : 8,
 ///         },
+///         boards: vec![
+///             BoardParams {
+///                 id                 : String::from("dev"),
+///                 name               : String::from("Dev board"),
+///                 history_size       : 256,
+///                 timezone           : String::from("UTC"),
+///                 max_message_length : 512,
+///                 refresh_interval   : 8,
+///             },
+///         ],
 ///         storage: StorageParams {
-///             data_dir : String::from("data"),
+///             backend        : StorageKind::Csv,
+///             data_dir       : String::from("data"),
+///             fsync          : FsyncPolicy::Interval,
+///             fsync_interval : 1,
+///             write_queue    : 1024,
+///             rotation       : RotationPolicy::Never,
+///             segment_size   : 16,
+///             compress       : false,
+///         },
+///         moderation: ModerationParams {
+///             tokens : vec![ String::from("s3cr3t") ],
 ///         },
+///         limits: LimitsParams::default(),
 /// };
 /// assert_eq!(cfg.board.name,         String::from("oxyboard"));
 /// assert_eq!(cfg.board.history_size, 512);
+/// assert_eq!(cfg.boards.len(),       1);
 /// assert_eq!(cfg.storage.data_dir,   String::from("data"));
 /// ```
-#[derive(Debug,Deserialize)]
+#[derive(Debug,Deserialize,Serialize)]
 pub struct Config {
 	pub board   : BoardParams,
+	#[serde(default, deserialize_with = "deserialize_boards")]
+	pub boards  : Vec<BoardParams>,
 	pub storage : StorageParams,
+	#[serde(default)]
+	pub moderation : ModerationParams,
+	#[serde(default)]
+	pub limits  : LimitsParams,
 }
 
 
 /// The board parameters define the served board (name, history size...)
 ///
+/// The `id` is the key of the board in the URLs (`/b/<id>/backend`) and the
+/// name of its data sub-directory. It defaults to `"default"` for the default
+/// board, and is mandatory for the additional ones.
+///
+/// The `timezone` is the one the times of the posts are displayed in:
+/// `"local"` (the timezone of the server, by default), `"UTC"` or an offset
+/// like `"+02:00"`.
+///
+/// The `max_message_length` is the maximum number of characters of a message
+/// (512 by default), longer messages being cut. The `refresh_interval` is the
+/// delay in seconds between two polls of the backend advised to the clients
+/// (8 by default).
+///
 /// # Examples
 ///
 /// ```
 /// use oxyboard::config::BoardParams;
 ///
 /// let board_cfg = BoardParams {
-///         name         : String::from("oxyboard"),
-///         history_size : 512,
+///         id                 : String::from("default"),
+///         name               : String::from("oxyboard"),
+///         history_size       : 512,
+///         timezone           : String::from("+02:00"),
+///         max_message_length : 512,
+///         refresh_interval   : 8,
 /// };
+/// assert_eq!(board_cfg.id,           String::from("default"));
 /// assert_eq!(board_cfg.name,         String::from("oxyboard"));
 /// assert_eq!(board_cfg.history_size, 512);
 /// ```
-#[derive(Debug,Deserialize)]
+#[derive(Debug,Deserialize,Serialize)]
 pub struct BoardParams {
-	pub name         : String,
-	pub history_size : usize,
+	#[serde(default = "default_board_id")]
+	pub id                 : String,
+	pub name               : String,
+	pub history_size       : usize,
+	#[serde(default = "default_timezone")]
+	pub timezone           : String,
+	#[serde(default = "default_max_message_length")]
+	pub max_message_length : usize,
+	#[serde(default = "default_refresh_interval")]
+	pub refresh_interval   : u64,
 }
 
 
 /// The storage parameters define where and how the data are saved.
 ///
+/// The `backend` defaults to CSV files.
+///
+/// The new posts are written in the background: they wait in a queue of
+/// `write_queue` posts (1024 by default) and are written together. The
+/// `fsync` policy tells when they are flushed to the disk: after each write
+/// (`always`), every `fsync_interval` seconds (`interval`, by default every
+/// second) or when the system decides (`never`).
+///
+/// The `rotation` policy tells how the CSV or JSON Lines history is split
+/// into segments: not at all (`never`, by default), by month (`monthly`) or
+/// when a segment reaches `segment_size` megabytes (`size`, 16 by default,
+/// and never 0).
+/// With `compress`, the segments are compressed with gzip once closed.
+///
 /// # Examples
 ///
 /// ```
-/// use oxyboard::config::StorageParams;
+/// use oxyboard::config::{ FsyncPolicy, RotationPolicy, StorageParams, StorageKind };
 ///
 /// let storage_cfg = StorageParams {
-///         data_dir : String::from("data"),
+///         backend        : StorageKind::Sqlite,
+///         data_dir       : String::from("data"),
+///         fsync          : FsyncPolicy::Always,
+///         fsync_interval : 1,
+///         write_queue    : 1024,
+///         rotation       : RotationPolicy::Monthly,
+///         segment_size   : 16,
+///         compress       : true,
 /// };
+/// assert_eq!(storage_cfg.backend,  StorageKind::Sqlite);
 /// assert_eq!(storage_cfg.data_dir, String::from("data"));
 /// ```
-#[derive(Debug,Deserialize)]
+#[derive(Debug,Deserialize,Serialize)]
 pub struct StorageParams {
-	pub data_dir : String,
+	#[serde(default)]
+	pub backend        : StorageKind,
+	pub data_dir       : String,
+	#[serde(default)]
+	pub fsync          : FsyncPolicy,
+	#[serde(default = "default_fsync_interval")]
+	pub fsync_interval : u64,
+	#[serde(default = "default_write_queue")]
+	pub write_queue    : usize,
+	#[serde(default)]
+	pub rotation       : RotationPolicy,
+	#[serde(default = "default_segment_size")]
+	pub segment_size   : u64,
+	#[serde(default)]
+	pub compress       : bool,
+}
+
+
+/// The available storage backends, named in lowercase in the configuration
+/// (`backend = "sqlite"`).
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+	/// A CSV file per board
+	#[default]
+	Csv,
+	/// A JSON Lines file per board
+	Jsonl,
+	/// An SQLite database per board
+	Sqlite,
+}
+
+
+/// The policies of the flushes of the written data to the disk, named in
+/// lowercase in the configuration (`fsync = "always"`).
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FsyncPolicy {
+	/// After each write
+	Always,
+	/// Periodically
+	#[default]
+	Interval,
+	/// Never, the system flushes the data when it decides to
+	Never,
+}
+
+
+/// The policies of the rotation of the history files, named in lowercase
+/// in the configuration (`rotation = "monthly"`).
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationPolicy {
+	/// A single file
+	#[default]
+	Never,
+	/// A file per month
+	Monthly,
+	/// A new file when the current one is too big
+	Size,
+}
+
+
+/// The moderation parameters list the secret tokens of the moderators.
+///
+/// A request is a moderator's one when its `Authorization` header is
+/// `Bearer <token>` with one of these tokens. Without any token, nobody can
+/// moderate the boards.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::config::ModerationParams;
+///
+/// let moderation_cfg = ModerationParams::default();
+/// assert!(moderation_cfg.tokens.is_empty());
+/// ```
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ModerationParams {
+	#[serde(default)]
+	pub tokens : Vec<String>,
+}
+
+
+/// The limits parameters define the rates of the posts of the clients.
+///
+/// The posts can be limited by IP address, by login and by User-Agent, each
+/// with its own token bucket. Only the IP addresses are limited by default,
+/// to 10 posts at once and 30 posts per minute. A rate with a null `burst`
+/// disables the limit.
+///
+/// The attempts to log in or to register are limited apart, by IP address
+/// (`account`), to 5 at once and 5 per minute by default, as each one costs
+/// a password hash.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::config::LimitsParams;
+///
+/// let limits_cfg = LimitsParams::default();
+/// assert_eq!(limits_cfg.ip.burst,           10);
+/// assert_eq!(limits_cfg.ip.per_minute,      30);
+/// assert_eq!(limits_cfg.login.burst,        0);
+/// assert_eq!(limits_cfg.user_agent.burst,   0);
+/// assert_eq!(limits_cfg.account.per_minute, 5);
+/// ```
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LimitsParams {
+	#[serde(default = "default_ip_rate")]
+	pub ip         : RateParams,
+	#[serde(default)]
+	pub login      : RateParams,
+	#[serde(default)]
+	pub user_agent : RateParams,
+	#[serde(default = "default_account_rate")]
+	pub account    : RateParams,
+}
+
+impl Default for LimitsParams {
+	fn default() -> LimitsParams {
+		LimitsParams {
+			ip         : default_ip_rate(),
+			login      : RateParams::default(),
+			user_agent : RateParams::default(),
+			account    : default_account_rate(),
+		}
+	}
+}
+
+
+/// A token bucket rate: `burst` posts at once, then `per_minute` posts per minute.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct RateParams {
+	pub burst      : u32,
+	pub per_minute : u32,
+}
+
+
+/// The default rate of the posts of an IP address.
+pub fn default_ip_rate() -> RateParams {
+	RateParams {
+		burst      : 10,
+		per_minute : 30,
+	}
+}
+
+
+/// Reads the additional boards, which must all have an id.
+///
+/// Otherwise they would get the id of the default board, and share its data.
+fn deserialize_boards<'de, D: serde::Deserializer<'de>>(p_deserializer: D) -> Result<Vec<BoardParams>, D::Error> {
+	use serde::de::Error;
+
+	let boards: Vec<toml::Value> = serde::Deserialize::deserialize(p_deserializer)?;
+	boards.into_iter()
+			.map(|board| match board.get("id") {
+				Some(_) => board.try_into().map_err(D::Error::custom),
+				None    => Err(D::Error::custom("missing field `id` in a [[boards]] entry"))
+			})
+			.collect()
+}
+
+
+/// The default rate of the attempts of an IP address to log in or to register.
+pub fn default_account_rate() -> RateParams {
+	RateParams {
+		burst      : 5,
+		per_minute : 5,
+	}
+}
+
+
+/// The timezone of a board which does not declare one.
+pub fn default_timezone() -> String {
+	String::from("local")
+}
+
+
+/// The maximum length of the messages of a board which does not declare one.
+pub fn default_max_message_length() -> usize {
+	512
+}
+
+
+/// The refresh interval of a board which does not declare one.
+pub fn default_refresh_interval() -> u64 {
+	8
+}
+
+
+/// The delay in seconds between two flushes of the storage, with the `interval` policy.
+pub fn default_fsync_interval() -> u64 {
+	1
+}
+
+
+/// The maximum number of posts waiting to be written.
+pub fn default_write_queue() -> usize {
+	1024
+}
+
+
+/// The size in megabytes of the history files, with the `size` rotation policy.
+pub fn default_segment_size() -> u64 {
+	16
+}
+
+
+/// The id given to a board which does not declare one.
+pub fn default_board_id() -> String {
+	String::from("default")
 }
@@ -1,4 +1,4 @@
-use crate::config::data::{ Config, BoardParams, StorageParams };
+use crate::config::data::{ Config, BoardParams, FsyncPolicy, LimitsParams, ModerationParams, RotationPolicy, StorageParams, StorageKind, default_board_id, default_fsync_interval, default_max_message_length, default_refresh_interval, default_segment_size, default_timezone, default_write_queue };
 
 /// Builds a default configuration.
 ///
@@ -8,19 +8,39 @@ use crate::config::data::{ Config, BoardParams, StorageParams };
 /// use oxyboard::config;
 ///
 /// let cfg = config::default();
+/// assert_eq!(cfg.board.id,           String::from("default"));
 /// assert_eq!(cfg.board.name,         String::from("oxyboard"));
 /// assert_eq!(cfg.board.history_size, 512);
+/// assert!(cfg.boards.is_empty());
 /// assert_eq!(cfg.storage.data_dir,   String::from("data"));
+/// assert!(cfg.moderation.tokens.is_empty());
 /// ```
 pub fn default() -> Config {
 	Config {
 		board : BoardParams {
-			name         : String::from("oxyboard"),
-			history_size : 512,
+			id                 : default_board_id(),
+			name               : String::from("oxyboard"),
+			history_size       : 512,
+			timezone           : default_timezone(),
+			max_message_length : default_max_message_length(),
+			refresh_interval   : default_refresh_interval(),
 		},
 
+		boards : Vec::new(),
+
 		storage: StorageParams {
-			data_dir : String::from("data"),
+			backend        : StorageKind::Csv,
+			data_dir       : String::from("data"),
+			fsync          : FsyncPolicy::default(),
+			fsync_interval : default_fsync_interval(),
+			write_queue    : default_write_queue(),
+			rotation       : RotationPolicy::default(),
+			segment_size   : default_segment_size(),
+			compress       : false,
 		},
+
+		moderation: ModerationParams::default(),
+
+		limits: LimitsParams::default(),
 	}
 }
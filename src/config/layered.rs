@@ -0,0 +1,205 @@
+//! Loads the configuration from several layers overriding each other.
+
+use crate::config::{Config, ConfigLoader};
+use crate::config::default;
+use std::io;
+use std::io::{Error as IoError, ErrorKind};
+use toml::Value;
+
+
+/// The prefix of the environment variables overriding the configuration.
+pub const ENV_PREFIX: &str = "OXYBOARD_";
+
+
+/// An override of a configuration field.
+struct Override {
+	/// The override as given, for the error messages
+	name  : String,
+	/// The path of the field, its sections being separated by underscores
+	key   : String,
+	/// The new value of the field, missing from a malformed override
+	value : Option<String>,
+}
+
+
+/// Loads the configuration of a base loader, then overrides its fields.
+///
+/// The layers are applied in this order, each one overriding the previous:
+///
+/// 1. the default configuration, used when the base loader finds no file,
+/// 2. the configuration of the base loader (usually a `TomlConfigLoader`),
+/// 3. the environment variables named `OXYBOARD_<SECTION>_<FIELD>`, like
+///    `OXYBOARD_BOARD_NAME` or `OXYBOARD_STORAGE_DATA_DIR`,
+/// 4. the overrides given as `<section>.<field>=<value>`, like
+///    `board.name=Plop`, usually from the command line.
+///
+/// The fields of the additional boards are named after their id, like
+/// `OXYBOARD_BOARDS_DEV_NAME` or `boards.dev.name=Plop`. The lists, like the
+/// moderation tokens, are given as comma-separated values.
+///
+/// Unlike a missing file, an invalid file or an unknown field is an error.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::config::{ ConfigLoader, LayeredConfigLoader, TomlConfigLoader };
+///
+/// let env = vec![
+///         (String::from("OXYBOARD_BOARD_NAME"),         String::from("From env")),
+///         (String::from("OXYBOARD_STORAGE_DATA_DIR"),   String::from("/var/lib/oxyboard")),
+///         (String::from("OXYBOARD_MODERATION_TOKENS"),  String::from("s3cr3t, t0k3n")),
+///         (String::from("HOME"),                        String::from("/root")),
+/// ];
+/// let cfg = LayeredConfigLoader::new(TomlConfigLoader::new(String::from("missing.toml")))
+///         .env(env)
+///         .overrides(vec!["board.name=From CLI", "limits.ip.burst=20"])
+///         .load()
+///         .unwrap();
+/// assert_eq!(cfg.board.name,        "From CLI");
+/// assert_eq!(cfg.storage.data_dir,  "/var/lib/oxyboard");
+/// assert_eq!(cfg.moderation.tokens, vec!["s3cr3t", "t0k3n"]);
+/// assert_eq!(cfg.limits.ip.burst,   20);
+///
+/// let unknown = LayeredConfigLoader::new(TomlConfigLoader::new(String::from("missing.toml")))
+///         .overrides(vec!["board.colour=blue"])
+///         .load();
+/// assert!(unknown.is_err());
+/// ```
+pub struct LayeredConfigLoader<L: ConfigLoader> {
+	base      : L,
+	env       : Vec<Override>,
+	overrides : Vec<Override>,
+}
+
+
+impl<L: ConfigLoader> LayeredConfigLoader<L> {
+	/// Creates a loader overriding the configuration of `p_base`.
+	pub fn new(p_base: L) -> LayeredConfigLoader<L> {
+		LayeredConfigLoader {
+			base      : p_base,
+			env       : Vec::new(),
+			overrides : Vec::new(),
+		}
+	}
+
+
+	/// Adds the environment variables, usually from `std::env::vars()`.
+	///
+	/// Only the variables starting with `OXYBOARD_` are kept.
+	pub fn env<I: IntoIterator<Item = (String, String)>>(mut self, p_vars: I) -> LayeredConfigLoader<L> {
+		self.env = p_vars.into_iter()
+				.filter_map(|(name, value)| name.strip_prefix(ENV_PREFIX)
+						.map(|key| key.to_lowercase())
+						.map(|key| Override { name, key, value: Some(value) }))
+				.collect();
+		self
+	}
+
+
+	/// Adds the `<section>.<field>=<value>` overrides.
+	///
+	/// A malformed override, without `=`, is reported by `load()`.
+	pub fn overrides<I, S>(mut self, p_overrides: I) -> LayeredConfigLoader<L>
+			where I: IntoIterator<Item = S>, S: AsRef<str> {
+		self.overrides = p_overrides.into_iter()
+				.map(|o| {
+					let name = o.as_ref().to_string();
+					let (key, value) = match name.split_once('=') {
+						Some((key, value)) => (key, Some(value.to_string())),
+						None               => (name.as_str(), None)
+					};
+					Override {
+						key   : key.trim().to_lowercase().replace('.', "_"),
+						value,
+						name  : name.clone(),
+					}
+				})
+				.collect();
+		self
+	}
+}
+
+
+impl<L: ConfigLoader> ConfigLoader for LayeredConfigLoader<L> {
+	/// Loads the base configuration and applies the overrides.
+	fn load(&self) -> io::Result<Config> {
+		let config = match self.base.load() {
+			Ok(c)                                     => c,
+			Err(e) if e.kind() == ErrorKind::NotFound => default(),
+			Err(e)                                    => return Err(e)
+		};
+		if self.env.is_empty() && self.overrides.is_empty() {
+			return Ok(config);
+		}
+
+		let mut fields = Value::try_from(&config).map_err(|e| IoError::new(ErrorKind::InvalidData, e.to_string()))?;
+		for over in self.env.iter().chain(self.overrides.iter()) {
+			let value = over.value.as_ref().ok_or_else(|| invalid_override(over, "expected <section>.<field>=<value>"))?;
+			set_field(&mut fields, &over.key, value).map_err(|e| invalid_override(over, &e))?;
+		}
+
+		fields.try_into().map_err(|e: toml::de::Error| IoError::new(ErrorKind::InvalidData, format!("Invalid configuration overrides: {}", e)))
+	}
+}
+
+
+/// Builds the error of an invalid override.
+fn invalid_override(p_override: &Override, p_reason: &str) -> IoError {
+	IoError::new(ErrorKind::InvalidInput, format!("Invalid configuration override '{}': {}", p_override.name, p_reason))
+}
+
+
+/// Sets the field at the path `p_key` of a configuration tree.
+///
+/// The sections of the path are separated by underscores, like the words of
+/// the names of the fields: the longest matching name is used at each level.
+/// The items of the lists of tables, like the additional boards, are named
+/// after their id.
+fn set_field(p_fields: &mut Value, p_key: &str, p_value: &str) -> Result<(), String> {
+	let matches = |name: &str| p_key == name || p_key.starts_with(&format!("{}_", name));
+
+	let field = match p_fields {
+		Value::Table(table) => {
+			let name = table.keys()
+					.filter(|k| matches(k))
+					.max_by_key(|k| k.len())
+					.cloned()
+					.ok_or_else(|| String::from("unknown field"))?;
+			table.get_mut(&name).map(|f| (name.len(), f))
+		},
+		Value::Array(items) => items.iter_mut()
+				.filter_map(|item| {
+					let id = item.get("id")?.as_str()?.to_lowercase().replace('-', "_");
+					if matches(&id) { Some((id.len(), item)) } else { None }
+				})
+				.max_by_key(|(length, _)| *length),
+		_ => None
+	};
+
+	let (length, field) = field.ok_or_else(|| String::from("unknown field"))?;
+	match p_key.get(length + 1..) {
+		Some(rest) if !rest.is_empty() => set_field(field, rest, p_value),
+		_                              => {
+			*field = parse_value(field, p_value)?;
+			Ok(())
+		}
+	}
+}
+
+
+/// Parses the new value of a field, as the type of its current value.
+fn parse_value(p_current: &Value, p_text: &str) -> Result<Value, String> {
+	let text = p_text.trim();
+	match p_current {
+		Value::String(_)  => Ok(Value::String(p_text.to_string())),
+		Value::Integer(_) => text.parse().map(Value::Integer).map_err(|_| format!("'{}' is not an integer", text)),
+		Value::Float(_)   => text.parse().map(Value::Float).map_err(|_| format!("'{}' is not a number", text)),
+		Value::Boolean(_) => text.parse().map(Value::Boolean).map_err(|_| format!("'{}' is not a boolean", text)),
+		Value::Array(_)   => Ok(Value::Array(text.split(',')
+				.map(str::trim)
+				.filter(|v| !v.is_empty())
+				.map(|v| Value::String(v.to_string()))
+				.collect())),
+		_                 => Err(String::from("a whole section cannot be set"))
+	}
+}
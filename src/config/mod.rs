@@ -2,8 +2,15 @@
 /// The configuration data structure and loaders.
 pub use self::data::Config;
 pub use self::data::BoardParams;
+pub use self::data::FsyncPolicy;
+pub use self::data::ModerationParams;
+pub use self::data::LimitsParams;
+pub use self::data::RateParams;
+pub use self::data::RotationPolicy;
 pub use self::data::StorageParams;
+pub use self::data::StorageKind;
 pub use self::default::default;
+pub use self::layered::LayeredConfigLoader;
 pub use self::loader::ConfigLoader;
 pub use self::toml::TomlConfigLoader;
 
@@ -11,5 +18,6 @@ pub use self::toml::TomlConfigLoader;
 pub mod data;
 pub mod loader;
 pub mod default;
+pub mod layered;
 pub mod toml;
 
@@ -0,0 +1,255 @@
+//! The user accounts.
+
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::password_hash::rand_core::OsRng;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::{OnceLock, RwLock};
+
+
+/// The maximum length of a login.
+pub const MAX_LOGIN_LENGTH: usize = 32;
+
+/// The minimum length of a password.
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+
+/// An `AccountRegistry` holds the accounts of the users.
+///
+/// The passwords are hashed with Argon2, only their PHC string (which holds
+/// the salt and the parameters of the hash) is kept.
+///
+/// Hashing a password takes a while: the request handlers get the hash of an
+/// account with `password_hash()`, then check it with `verify_password()` out
+/// of the async threads and without holding the lock of the registry.
+///
+/// The accounts can be recorded in a CSV file, each account being a line of
+/// the file (`login,password hash`). The file is only appended to.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::core::accounts::{ AccountRegistry, verify_password };
+///
+/// let accounts = AccountRegistry::new();
+/// accounts.register("bob", "correct horse").unwrap();
+///
+/// assert!(accounts.exists("bob"));
+/// assert!(accounts.verify("bob", "correct horse"));
+/// assert!(!accounts.verify("bob", "battery staple"));
+/// assert!(!accounts.verify("alice", "correct horse"));
+///
+/// // The hash can be checked apart from the registry
+/// let hash = accounts.password_hash("bob");
+/// assert!(verify_password(hash.as_deref(), "correct horse"));
+/// assert!(!verify_password(None, "correct horse"));
+///
+/// // Logins are unique
+/// assert!(accounts.register("bob", "battery staple").is_err());
+/// ```
+#[derive(Default)]
+pub struct AccountRegistry {
+	/// Path of the accounts file, if they are recorded
+	path     : Option<String>,
+	/// The password hashes, by login
+	accounts : RwLock<HashMap<String, String>>,
+}
+
+impl AccountRegistry {
+	/// Constructs an empty registry, which does not record its accounts.
+	pub fn new() -> AccountRegistry {
+		AccountRegistry::default()
+	}
+
+
+	/// Loads the accounts recorded in the `p_file` CSV file of the `p_dir`
+	/// directory, where the new accounts will be recorded too.
+	///
+	/// A missing file has no accounts.
+	pub fn load(p_dir: &str, p_file: &str) -> io::Result<AccountRegistry> {
+		let path = format!("{dir}/{file}",
+				dir  = p_dir,
+				file = p_file);
+
+		let mut accounts = HashMap::new();
+		if Path::new(&path).exists() {
+			let mut reader = csv::ReaderBuilder::new()
+					.has_headers(false)
+					.from_path(&path)?;
+
+			for line in reader.deserialize() {
+				match line {
+					Ok((login, hash)) => { accounts.insert(login, hash); },
+					Err(err)          => warn_msg!("Failed to parse accounts file '{}': {}", path, &err)
+				}
+			}
+		}
+
+		Ok(AccountRegistry {
+			path     : Some(path),
+			accounts : RwLock::new(accounts),
+		})
+	}
+
+
+	/// Gives the number of accounts.
+	pub fn len(&self) -> usize {
+		self.accounts.read().unwrap().len()
+	}
+
+
+	/// Tells whether there is no account.
+	pub fn is_empty(&self) -> bool {
+		self.accounts.read().unwrap().is_empty()
+	}
+
+
+	/// Tells whether an account exists.
+	pub fn exists(&self, p_login: &str) -> bool {
+		self.accounts.read().unwrap().contains_key(p_login)
+	}
+
+
+	/// Creates an account.
+	///
+	/// The login must be made of 1 to `MAX_LOGIN_LENGTH` letters, digits, `-`
+	/// or `_`, and the password must have at least `MIN_PASSWORD_LENGTH`
+	/// characters, otherwise an `InvalidInput` error is returned. An
+	/// `AlreadyExists` error is returned if the login is taken.
+	pub fn register(&self, p_login: &str, p_password: &str) -> io::Result<()> {
+		check_login(p_login)?;
+		self.add(p_login, hash_password(p_password)?)
+	}
+
+
+	/// Creates an account from the hash of its password, given by
+	/// `hash_password()`.
+	///
+	/// Returns an `InvalidInput` error if the login is invalid, and an
+	/// `AlreadyExists` error if it is taken.
+	pub fn add(&self, p_login: &str, p_hash: String) -> io::Result<()> {
+		check_login(p_login)?;
+
+		let mut accounts = self.accounts.write().unwrap();
+		if accounts.contains_key(p_login) {
+			return Err(io::Error::new(ErrorKind::AlreadyExists, format!("The login '{}' is already taken", p_login)));
+		}
+
+		self.record(p_login, &p_hash)?;
+		accounts.insert(p_login.to_string(), p_hash);
+		Ok(())
+	}
+
+
+	/// Gives the password hash of an account, if it exists.
+	pub fn password_hash(&self, p_login: &str) -> Option<String> {
+		self.accounts.read().unwrap().get(p_login).cloned()
+	}
+
+
+	/// Checks the password of an account.
+	///
+	/// The lock of the registry is released before the password is hashed.
+	pub fn verify(&self, p_login: &str, p_password: &str) -> bool {
+		verify_password(self.password_hash(p_login).as_deref(), p_password)
+	}
+
+
+	/// Appends an account to the accounts file, if any.
+	fn record(&self, p_login: &str, p_hash: &str) -> io::Result<()> {
+		let path = match &self.path {
+			Some(path) => path,
+			None       => return Ok(())
+		};
+
+		if let Some(dir) = Path::new(path).parent() {
+			fs::create_dir_all(dir)?;
+		}
+		let mut writer = csv::Writer::from_writer(
+				OpenOptions::new()
+					.create(true)
+					.append(true)
+					.open(path)? );
+
+		writer.serialize((p_login, p_hash)).map_err(|e| io::Error::other(format!("Failed to record the account in '{}': {}", path, e)))?;
+		writer.flush()
+	}
+}
+
+
+/// Hashes a password, with a new salt.
+///
+/// The password must have at least `MIN_PASSWORD_LENGTH` characters,
+/// otherwise an `InvalidInput` error is returned.
+pub fn hash_password(p_password: &str) -> io::Result<String> {
+	if p_password.chars().count() < MIN_PASSWORD_LENGTH {
+		return Err(io::Error::new(ErrorKind::InvalidInput, format!("The password must have at least {} characters", MIN_PASSWORD_LENGTH)));
+	}
+
+	let salt = SaltString::generate(&mut OsRng);
+	Argon2::default()
+			.hash_password(p_password.as_bytes(), &salt)
+			.map(|h| h.to_string())
+			.map_err(|e| io::Error::other(format!("Failed to hash the password: {}", e)))
+}
+
+
+/// Checks a password against the hash of an account.
+///
+/// Without an account, the password is checked against a dummy hash, so that
+/// the time of the answer does not tell whether the login exists.
+pub fn verify_password(p_hash: Option<&str>, p_password: &str) -> bool {
+	let (hash, exists) = match p_hash {
+		Some(hash) => (hash, true),
+		None       => (dummy_hash(), false)
+	};
+
+	let valid = match PasswordHash::new(hash) {
+		Ok(hash) => Argon2::default().verify_password(p_password.as_bytes(), &hash).is_ok(),
+		Err(_)   => false
+	};
+	valid && exists
+}
+
+
+/// Gives the hash checked for the logins without an account, made once with
+/// the same parameters as the real ones.
+fn dummy_hash() -> &'static str {
+	static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+	DUMMY_HASH.get_or_init(|| {
+		let salt = SaltString::generate(&mut OsRng);
+		Argon2::default()
+				.hash_password(b"not the password of any account", &salt)
+				.map(|h| h.to_string())
+				.unwrap_or_default()
+	})
+}
+
+
+/// Checks the characters and the length of a login.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::core::accounts::check_login;
+///
+/// assert!(check_login("ptramo").is_ok());
+/// assert!(check_login("").is_err());
+/// assert!(check_login("bob<script>").is_err());
+/// ```
+pub fn check_login(p_login: &str) -> io::Result<()> {
+	let length = p_login.chars().count();
+	if length == 0 || length > MAX_LOGIN_LENGTH {
+		return Err(io::Error::new(ErrorKind::InvalidInput, format!("The login must have 1 to {} characters", MAX_LOGIN_LENGTH)));
+	}
+
+	if !p_login.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+		return Err(io::Error::new(ErrorKind::InvalidInput, "The login can only contain letters, digits, '-' and '_'"));
+	}
+	Ok(())
+}
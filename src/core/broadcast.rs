@@ -0,0 +1,91 @@
+//! The broadcasting of the history events to asynchronous subscribers.
+
+use crate::core::history::{HistoryListener, Moderation};
+use crate::core::post::Post;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+
+/// The events sent by a `PostBroadcaster` to its subscribers.
+#[derive(Clone)]
+pub enum BoardEvent {
+	/// A new post has been added to the history.
+	PostAdded(Arc<Post>),
+	/// A post has been deleted, or masked and replaced by the given copy.
+	PostDeleted(Arc<Post>, Moderation),
+}
+
+
+/// An history listener that fans out the new posts to any number of subscribers.
+///
+/// Each subscriber gets its own `broadcast::Receiver`. A subscriber that falls
+/// behind by more than the capacity of the channel misses the oldest events;
+/// it is then told so by a `RecvError::Lagged` and should catch up from the
+/// `History` itself.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::core::{History, PostBroadcaster, UserPost};
+/// use oxyboard::core::broadcast::BoardEvent;
+///
+/// let broadcaster = PostBroadcaster::new(16);
+/// let mut receiver = broadcaster.subscribe();
+///
+/// let mut hist = History::new(&String::from("Oxyboard"), 512);
+/// hist.add_listener(Box::new(broadcaster.clone()));
+/// hist.add_post(UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Plop!")));
+///
+/// match receiver.try_recv() {
+///     Ok(BoardEvent::PostAdded(post)) => assert_eq!(post.message(), "Plop!"),
+///     _                               => panic!("No event received"),
+/// }
+/// ```
+#[derive(Clone)]
+pub struct PostBroadcaster {
+	sender: broadcast::Sender<BoardEvent>,
+}
+
+impl PostBroadcaster {
+	/// Constructs a broadcaster keeping at most `p_capacity` events for its late subscribers.
+	pub fn new(p_capacity: usize) -> PostBroadcaster {
+		let (sender, _) = broadcast::channel(p_capacity);
+		PostBroadcaster {
+			sender
+		}
+	}
+
+
+	/// Creates a new subscription to the events.
+	///
+	/// Only the events sent after the subscription are received.
+	pub fn subscribe(&self) -> broadcast::Receiver<BoardEvent> {
+		self.sender.subscribe()
+	}
+}
+
+
+impl HistoryListener for PostBroadcaster {
+	/// Sends the post to the current subscribers, if any.
+	fn post_added(&self, p_post: &Post) {
+		if self.sender.receiver_count() > 0 {
+			// Sending can only fail when the last subscriber just left
+			let _ = self.sender.send(BoardEvent::PostAdded(Arc::new(p_post.clone())));
+		}
+	}
+
+
+	/// Does nothing.
+	fn post_removed(&self, _: &Post) {
+		// Nothing to do
+	}
+
+
+	/// Sends the moderated post to the current subscribers, if any.
+	fn post_deleted(&self, p_post: &Post, p_moderation: Moderation) {
+		if self.sender.receiver_count() > 0 {
+			// Sending can only fail when the last subscriber just left
+			let _ = self.sender.send(BoardEvent::PostDeleted(Arc::new(p_post.clone()), p_moderation));
+		}
+	}
+}
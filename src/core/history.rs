@@ -2,9 +2,11 @@
 /// The history container and listener.
 
 use crate::core::{Post, UserPost};
+use crate::core::norloge::{Norloge, ReplyIndex};
+use crate::core::timezone::Timezone;
 use std::collections::vec_deque::{VecDeque, Iter};
 use std::sync::RwLock;
-use chrono::Local;
+use chrono::{DateTime, Utc};
 
 
 #[derive(Deserialize)]
@@ -22,15 +24,30 @@ pub struct HistoryData {
 /// the oldest one is removed to maintain a constant size.
 ///
 /// When a `Post` is added to the history, it gains a timestamp and an id.
+/// Its traditional time is the clock of that timestamp in the timezone of
+/// the board, the local one of the server by default. The posts of a same
+/// second are told apart by their index in that second.
 /// This id is generated by a sequence number held by the `History`.
 /// This sequence starts at 1 and is incremented for each post.
 ///
+/// The norloges of the posts are resolved when they are added, so that the
+/// `History` knows which posts answer each other.
+///
+/// The `History` also keeps a revision number, incremented each time a post is
+/// moderated, so that the clients can tell when posts were changed.
+///
 /// Finally, an `History` can signal events to listeners. Those
 /// are implementors of the `HistoryListener` trait. It is usefull to hook
 /// external routines.
 pub struct History {
 	data : HistoryData,
 	next_post_id : u64,
+	replies      : ReplyIndex,
+	timezone     : Timezone,
+	last_second  : Option<(String, u32)>,
+	max_message_length : Option<usize>,
+	revision     : u64,
+	moderated_at : Option<DateTime<Utc>>,
 	events       : HistoryEventDispatcher
 }
 
@@ -57,6 +74,12 @@ impl History {
 				max_size   : p_max_size,
 			},
 			next_post_id : 1,
+			replies : ReplyIndex::new(),
+			timezone : Timezone::default(),
+			last_second : None,
+			max_message_length : None,
+			revision : 0,
+			moderated_at : None,
 			events : HistoryEventDispatcher::new()
 		}
 	}
@@ -78,10 +101,113 @@ impl History {
 	}
 
 
+	/// Gives the maximum number of posts kept in the history.
+	pub fn max_size(&self) -> usize {
+		self.data.max_size
+	}
+
+
+	/// Gives the timezone of the board.
+	pub fn timezone(&self) -> &Timezone {
+		&self.timezone
+	}
+
+
+	/// Sets the timezone of the board.
+	///
+	/// It should be set before any post is added, as only the new posts are
+	/// dated in this timezone.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::{History, UserPost};
+	///
+	/// let mut hist = History::new(&String::from("Oxyboard"), 512);
+	/// hist.set_timezone("UTC".parse().unwrap());
+	/// let post_id = hist.add_post(UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Plop!")));
+	///
+	/// let post = hist.get(post_id).unwrap();
+	/// assert_eq!(post.time(), &post.instant().format("%Y%m%d%H%M%S").to_string());
+	/// ```
+	pub fn set_timezone(&mut self, p_timezone: Timezone) {
+		self.timezone = p_timezone;
+	}
+
+
+	/// Gives the maximum number of characters of the messages, if any.
+	pub fn max_message_length(&self) -> Option<usize> {
+		self.max_message_length
+	}
+
+
+	/// Sets the maximum number of characters of the messages.
+	///
+	/// The longer messages are cut when they are added.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::{History, UserPost};
+	///
+	/// let mut hist = History::new(&String::from("Oxyboard"), 512);
+	/// hist.set_max_message_length(4);
+	/// let post_id = hist.add_post(UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Plop plop")));
+	/// assert_eq!(hist.get(post_id).unwrap().message(), "Plop");
+	/// ```
+	pub fn set_max_message_length(&mut self, p_length: usize) {
+		self.max_message_length = Some(p_length);
+	}
+
+
+	/// Gives the revision of the history, the number of posts moderated
+	/// since it was created.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::{History, UserPost};
+	/// use oxyboard::core::history::Moderation;
+	///
+	/// let mut hist = History::new(&String::from("Oxyboard"), 512);
+	/// let post_id = hist.add_post(UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Plop!")));
+	/// assert_eq!(hist.revision(), 0);
+	///
+	/// hist.moderate_post(post_id, Moderation::Mask);
+	/// assert_eq!(hist.revision(), 1);
+	/// ```
+	pub fn revision(&self) -> u64 {
+		self.revision
+	}
+
+
+	/// Gives the last time the history was changed: the time of its last
+	/// post, or of the last moderation if it is more recent.
+	///
+	/// `None` if the history is empty and was never moderated.
+	pub fn last_modified(&self) -> Option<DateTime<Utc>> {
+		let last_post = self.data.posts.back().map(|p| *p.instant());
+		last_post.max(self.moderated_at)
+	}
+
+
+	/// Gives the id of the last post of the history, or 0 if it is empty.
+	pub fn last_post_id(&self) -> u64 {
+		self.data.posts.back().map_or(0, Post::id)
+	}
+
+
+	/// Gives the id the next post will get.
+	pub fn next_post_id(&self) -> u64 {
+		self.next_post_id
+	}
+
+
 	/// Appends a full post at the end of the history.
 	///
-	/// The given `Post` is added as-is. This function is meant to be used
-	/// when loading from storage data.
+	/// The given `Post` is added as-is, with its stored index in its second,
+	/// except for its time which follows the timezone of the board. This
+	/// function is meant to be used when loading from storage data.
 	///
 	/// The main differences with `add_post` are:
     ///
@@ -89,22 +215,49 @@ impl History {
 	/// * the internal next_post_id is set to the post's id + 1
 	/// * no listener is called
 	/// * it doesn't return anything
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::{History, Post, UserPost};
+	///
+	/// // The first post of the second was deleted: the second one keeps its index
+	/// let mut hist = History::new(&String::from("Oxyboard"), 512);
+	/// let post = Post::new(42, String::from("20161026120042"), UserPost::new(String::new(), String::new(), String::from("Plop!")));
+	/// hist.add_full_post(post.with_index(2));
+	/// assert_eq!(hist.get(42).unwrap().index(), 2);
+	/// assert_eq!(hist.next_post_id(), 43);
+	/// ```
 	pub fn add_full_post(&mut self, p_post: Post) {
-		// Remove the oldest post if the history will exceed its maximum size
-		if self.data.posts.len() >= self.data.max_size {
-			self.events.post_removed(&self.data.posts.pop_front().unwrap());
-		}
-
 		// Add the new post
 		let post_id = p_post.id();
-		self.data.posts.push_back(p_post);
-		self.events.post_added(&self.data.posts.back().unwrap());
+		self.push(p_post.in_timezone(&self.timezone));
 
 		// Increment the post id counter
 		self.next_post_id = post_id + 1;
 	}
 
 
+	/// Makes sure the ids up to `p_last_id` are never given to new posts.
+	///
+	/// This function is meant to be used when loading from storage data, when
+	/// the last stored posts have been deleted.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::{History, UserPost};
+	///
+	/// let mut hist = History::new(&String::from("Oxyboard"), 512);
+	/// hist.skip_post_ids(41);
+	/// let post_id = hist.add_post(UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Plop!")));
+	/// assert_eq!(post_id, 42);
+	/// ```
+	pub fn skip_post_ids(&mut self, p_last_id: u64) {
+		self.next_post_id = self.next_post_id.max(p_last_id + 1);
+	}
+
+
 	/// Appends a post at the end of the history.
 	///
 	/// The given `UserPost` is converted into a `Post` by giving it
@@ -136,22 +289,28 @@ impl History {
 	/// assert_eq!(post_id, 1);
 	/// assert_eq!(hist.size(), 1);
 	/// ```
-	pub fn add_post(&mut self, p_user_post: UserPost) -> u64 {
-		// Create the new Post
-		let post = Post::new(
+	pub fn add_post(&mut self, mut p_user_post: UserPost) -> u64 {
+		if let Some(length) = self.max_message_length {
+			if let Some((end, _)) = p_user_post.message.char_indices().nth(length) {
+				p_user_post.message.truncate(end);
+			}
+		}
+
+		// Create the new Post, numbered in its second
+		let post = Post::at(
 				self.next_post_id,
-				Local::now().format("%Y%m%d%H%M%S").to_string(),
+				Utc::now(),
+				&self.timezone,
 				p_user_post);
-
-		// Remove the oldest post if the history will exceed its maximum size
-		if self.data.posts.len() >= self.data.max_size {
-			self.events.post_removed(&self.data.posts.pop_front().unwrap());
-		}
+		let index = match &self.last_second {
+			Some((time, index)) if time == post.time() => index + 1,
+			_                                          => 1
+		};
+		let post = post.with_index(index);
 
 		// Add the new post
 		let post_id = post.id();
-		self.data.posts.push_back(post);
-		self.events.post_added(&self.data.posts.back().unwrap());
+		self.push(post);
 
 		// Increment the post id counter
 		self.next_post_id += 1;
@@ -159,6 +318,138 @@ impl History {
 	}
 
 
+	/// Appends a post, removing the oldest one if the history is already full.
+	///
+	/// The norloges of the post are resolved before it is added, so that it
+	/// cannot reference itself.
+	fn push(&mut self, p_post: Post) {
+		// Remember the second of the post, to number the next ones
+		self.last_second = Some((p_post.time().clone(), p_post.index()));
+
+		// Remove the oldest post if the history will exceed its maximum size
+		if self.data.posts.len() >= self.data.max_size {
+			let oldest = self.data.posts.pop_front().unwrap();
+			self.replies.remove(oldest.id());
+			self.events.post_removed(&oldest);
+		}
+
+		// Index the posts referenced by the new one
+		let mut references: Vec<u64> = Norloge::parse(p_post.message()).iter()
+			.flat_map(|n| n.resolve(self))
+			.collect();
+		references.sort_unstable();
+		references.dedup();
+		self.replies.add(p_post.id(), references);
+
+		// Add the new post
+		self.data.posts.push_back(p_post);
+		self.events.post_added(self.data.posts.back().unwrap());
+	}
+
+
+	/// Deletes or masks a post of the history.
+	///
+	/// A deleted post is removed from the history, whereas a masked one keeps
+	/// its place with its message replaced. The listeners are then notified
+	/// with the `post_deleted` event.
+	///
+	/// Returns the post as it was moderated (the masked copy when masking), or
+	/// `None` if it is not in the history.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::{History, UserPost};
+	/// use oxyboard::core::history::Moderation;
+	/// use oxyboard::core::post::MASKED_MESSAGE;
+	///
+	/// let mut hist = History::new(&String::from("Oxyboard"), 512);
+	/// let spam  = hist.add_post(UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Buy cheap stuff!")));
+	/// let abuse = hist.add_post(UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("You moron!")));
+	///
+	/// assert!(hist.moderate_post(spam, Moderation::Delete).is_some());
+	/// assert!(hist.get(spam).is_none());
+	///
+	/// assert!(hist.moderate_post(abuse, Moderation::Mask).is_some());
+	/// assert_eq!(hist.get(abuse).unwrap().message(), MASKED_MESSAGE);
+	///
+	/// assert!(hist.moderate_post(spam, Moderation::Delete).is_none());
+	/// ```
+	pub fn moderate_post(&mut self, p_post_id: u64, p_moderation: Moderation) -> Option<Post> {
+		let index = self.data.posts.binary_search_by_key(&p_post_id, |p| p.id()).ok()?;
+
+		let moderated = match p_moderation {
+			Moderation::Delete => {
+				self.replies.remove(p_post_id);
+				self.data.posts.remove(index)?
+			},
+			Moderation::Mask => {
+				let masked = self.data.posts[index].masked();
+				self.data.posts[index] = masked.clone();
+				masked
+			}
+		};
+
+		self.revision += 1;
+		self.moderated_at = Some(Utc::now());
+		self.events.post_deleted(&moderated, p_moderation);
+		Some(moderated)
+	}
+
+
+	/// Gives the post with the given id, if it is still in the history.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::{History, UserPost};
+	///
+	/// let mut hist = History::new(&String::from("Oxyboard"), 512);
+	/// let post_id = hist.add_post(UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Plop!")));
+	///
+	/// assert_eq!(hist.get(post_id).unwrap().message(), "Plop!");
+	/// assert!(hist.get(post_id + 1).is_none());
+	/// ```
+	pub fn get(&self, p_post_id: u64) -> Option<&Post> {
+		// The posts are sorted by id
+		self.data.posts.binary_search_by_key(&p_post_id, |p| p.id())
+			.ok()
+			.map(|i| &self.data.posts[i])
+	}
+
+
+	/// Gives the posts of the history referenced by the norloges of a post.
+	pub fn references(&self, p_post_id: u64) -> Vec<&Post> {
+		self.replies.references(p_post_id).iter()
+			.filter_map(|id| self.get(*id))
+			.collect()
+	}
+
+
+	/// Gives the posts of the history whose norloges reference a post.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::{History, Post, UserPost};
+	///
+	/// let mut hist = History::new(&String::from("Oxyboard"), 512);
+	/// let question = UserPost::new(String::from("bob"), String::from("Firefox/48.0.1"), String::from("Hello !"));
+	/// hist.add_full_post(Post::new(1, String::from("20161026120042"), question));
+	/// let answer = UserPost::new(String::from("alice"), String::from("Firefox/48.0.1"), String::from("12:00:42 Hi !"));
+	/// hist.add_full_post(Post::new(2, String::from("20161026120131"), answer));
+	///
+	/// assert_eq!(hist.replies(1)[0].id(),    2);
+	/// assert_eq!(hist.references(2)[0].id(), 1);
+	/// assert!(hist.replies(2).is_empty());
+	/// ```
+	pub fn replies(&self, p_post_id: u64) -> Vec<&Post> {
+		self.replies.replies(p_post_id).iter()
+			.filter_map(|id| self.get(*id))
+			.collect()
+	}
+
+
 	/// Returns an immutable iterator on the posts.
 	pub fn iter(&self) -> Iter<Post> {
 		self.data.posts.iter()
@@ -172,18 +463,35 @@ impl History {
 }
 
 
+/// The ways a moderator can get rid of a post.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Moderation {
+	/// The post is removed from the board
+	Delete,
+	/// The message of the post is replaced by `post::MASKED_MESSAGE`
+	Mask,
+}
+
+
 /// A `HistoryListener` is the interface for listening `History` events.
 ///
 /// Currently, this includes:
 ///
 /// * `post_added`   - A new message has been added to the history.
 /// * `post_removed` - The oldest message has been removed from the history.
+/// * `post_deleted` - A message has been deleted or masked by a moderator.
 pub trait HistoryListener {
 	/// Notifies a new post has been added to the history.
 	fn post_added(&self, p_post: &Post);
 
 	/// Notifies the oldest post has been removed from the history.
 	fn post_removed(&self, p_post: &Post);
+
+	/// Notifies a post has been moderated.
+	///
+	/// The post is the deleted one, or the masked copy which replaced it.
+	fn post_deleted(&self, p_post: &Post, p_moderation: Moderation);
 }
 
 
@@ -226,6 +534,14 @@ impl HistoryListener for HistoryEventDispatcher {
 			listener.post_removed(p_post);
 		}
 	}
+
+
+	/// Dispatches the `post_deleted(p_post, p_moderation)` to every registered listener.
+	fn post_deleted(&self, p_post: &Post, p_moderation: Moderation) {
+		for listener in &self.listeners {
+			listener.post_deleted(p_post, p_moderation);
+		}
+	}
 }
 
 pub type LockedHistory = RwLock<History>;
@@ -0,0 +1,163 @@
+//! The rate limiting of the posts.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+
+/// The number of buckets above which the full ones are forgotten.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+
+/// What a rate limit applies to.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum LimitKind {
+	/// The IP address of the client
+	Ip,
+	/// The login of an authenticated user
+	Login,
+	/// The User-Agent of the client
+	UserAgent,
+	/// The IP address of a client logging in or registering
+	Account,
+}
+
+
+/// A token bucket rate: `burst` posts at once, refilled at `per_minute` posts
+/// per minute.
+#[derive(Clone, Copy, Debug)]
+pub struct Rate {
+	pub burst      : u32,
+	pub per_minute : u32,
+}
+
+
+/// The tokens left to a client.
+struct Bucket {
+	tokens  : f64,
+	updated : Instant,
+}
+
+impl Bucket {
+	/// Adds the tokens earned since the last update, up to the burst.
+	fn refill(&mut self, p_rate: &Rate, p_now: Instant) {
+		let elapsed = p_now.saturating_duration_since(self.updated).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * p_rate.per_minute as f64 / 60.0).min(p_rate.burst as f64);
+		self.updated = p_now;
+	}
+
+
+	/// Gives the time to wait until a token is available.
+	fn wait(&self, p_rate: &Rate) -> Duration {
+		if self.tokens >= 1.0 {
+			Duration::ZERO
+		}
+		else if p_rate.per_minute == 0 {
+			Duration::MAX
+		}
+		else {
+			Duration::from_secs_f64((1.0 - self.tokens) * 60.0 / p_rate.per_minute as f64)
+		}
+	}
+}
+
+
+/// A `RateLimiter` limits the posts of the clients with token buckets.
+///
+/// Each limited kind has its own rate and a bucket per client key (an IP
+/// address, a login or a User-Agent). A post takes a token from each of the
+/// buckets of its client, and is only accepted if all of them have one.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::core::limiter::{LimitKind, Rate, RateLimiter};
+/// use std::time::{Duration, Instant};
+///
+/// let limiter = RateLimiter::new()
+///         .limit(LimitKind::Ip, Rate { burst: 2, per_minute: 6 });
+/// let now = Instant::now();
+///
+/// assert!(limiter.acquire(&[(LimitKind::Ip, "10.0.0.1")], now).is_ok());
+/// assert!(limiter.acquire(&[(LimitKind::Ip, "10.0.0.1")], now).is_ok());
+/// assert_eq!(limiter.acquire(&[(LimitKind::Ip, "10.0.0.1")], now), Err(Duration::from_secs(10)));
+///
+/// // Other clients are not affected, and the kinds without limit are ignored
+/// assert!(limiter.acquire(&[(LimitKind::Ip, "10.0.0.2"), (LimitKind::Login, "bob")], now).is_ok());
+///
+/// // A token is given back every 10 seconds
+/// assert!(limiter.acquire(&[(LimitKind::Ip, "10.0.0.1")], now + Duration::from_secs(10)).is_ok());
+/// ```
+#[derive(Default)]
+pub struct RateLimiter {
+	rates   : HashMap<LimitKind, Rate>,
+	buckets : Mutex<HashMap<(LimitKind, String), Bucket>>,
+}
+
+impl RateLimiter {
+	/// Constructs a limiter without any limit.
+	pub fn new() -> RateLimiter {
+		RateLimiter::default()
+	}
+
+
+	/// Sets the rate of a kind of limit.
+	pub fn limit(mut self, p_kind: LimitKind, p_rate: Rate) -> RateLimiter {
+		self.rates.insert(p_kind, p_rate);
+		self
+	}
+
+
+	/// Tells whether the limiter has no limit at all.
+	pub fn is_unlimited(&self) -> bool {
+		self.rates.is_empty()
+	}
+
+
+	/// Takes a token for a post from the buckets of the given client keys.
+	///
+	/// If one of the buckets is empty, no token is taken and the time to wait
+	/// before posting again is returned.
+	pub fn acquire(&self, p_keys: &[(LimitKind, &str)], p_now: Instant) -> Result<(), Duration> {
+		let mut buckets = self.buckets.lock().unwrap();
+		if buckets.len() > PRUNE_THRESHOLD {
+			self.prune(&mut buckets, p_now);
+		}
+
+		// Check all the buckets before taking any token
+		let mut wait = Duration::ZERO;
+		for (kind, key) in p_keys {
+			if let Some(rate) = self.rates.get(kind) {
+				let bucket = buckets.entry((*kind, key.to_string())).or_insert(Bucket {
+					tokens  : rate.burst as f64,
+					updated : p_now,
+				});
+				bucket.refill(rate, p_now);
+				wait = wait.max(bucket.wait(rate));
+			}
+		}
+
+		if wait > Duration::ZERO {
+			return Err(wait);
+		}
+
+		for (kind, key) in p_keys {
+			if let Some(bucket) = buckets.get_mut(&(*kind, key.to_string())) {
+				bucket.tokens -= 1.0;
+			}
+		}
+		Ok(())
+	}
+
+
+	/// Forgets the full buckets, which are the same as missing ones.
+	fn prune(&self, p_buckets: &mut HashMap<(LimitKind, String), Bucket>, p_now: Instant) {
+		p_buckets.retain(|(kind, _), bucket| match self.rates.get(kind) {
+			Some(rate) => {
+				bucket.refill(rate, p_now);
+				bucket.tokens < rate.burst as f64
+			},
+			None => false
+		});
+	}
+}
@@ -0,0 +1,226 @@
+//! The markup of the messages.
+//!
+//! The messages can use the traditional tribune subset of HTML: `<b>`, `<i>`,
+//! `<u>`, `<s>`, `<tt>` and `<a href="...">`. The normalized form of a
+//! message keeps these tags, balanced, escapes everything else and turns the
+//! bare URLs into links. It is safe to insert in an HTML page.
+
+
+/// The tags without attributes allowed in the messages.
+const SIMPLE_TAGS: [&str; 5] = ["b", "i", "u", "s", "tt"];
+
+/// The URL schemes allowed in the links.
+const URL_SCHEMES: [&str; 3] = ["http://", "https://", "ftp://"];
+
+
+/// Normalizes the markup of a message.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::core::markup::normalize;
+///
+/// assert_eq!(normalize("<b>plop</b> <script>alert(1)</script>"),
+///         "<b>plop</b> &lt;script&gt;alert(1)&lt;/script&gt;");
+///
+/// // The tags are balanced
+/// assert_eq!(normalize("<i>plop <b>coin"), "<i>plop <b>coin</b></i>");
+/// assert_eq!(normalize("plop</u>"),        "plop&lt;/u&gt;");
+///
+/// // Only the links with a web URL are kept
+/// assert_eq!(normalize("<a href=\"https://example.com\">site</a>"), "<a href=\"https://example.com\">site</a>");
+/// assert_eq!(normalize("<a href=\"javascript:alert(1)\">site</a>"),
+///         "&lt;a href=&quot;javascript:alert(1)&quot;&gt;site&lt;/a&gt;");
+///
+/// // The bare URLs become links
+/// assert_eq!(normalize("see https://example.com/a?b=1&c=2."),
+///         "see <a href=\"https://example.com/a?b=1&amp;c=2\">[url]</a>.");
+///
+/// // The non-ASCII texts are kept as they are
+/// assert_eq!(normalize("Ça déchire héhé"), "Ça déchire héhé");
+/// assert_eq!(normalize("abcdeé plop"),     "abcdeé plop");
+/// ```
+pub fn normalize(p_message: &str) -> String {
+	let mut output = String::with_capacity(p_message.len());
+	let mut open_tags: Vec<&str> = Vec::new();
+	let mut rest = p_message;
+	let mut previous: Option<char> = None;
+
+	while let Some(c) = rest.chars().next() {
+		if c == '<' {
+			if let Some((tag, length)) = Tag::parse(rest) {
+				match tag {
+					Tag::Open(name) if name != "a" || !open_tags.contains(&"a") => {
+						output.push('<');
+						output.push_str(name);
+						output.push('>');
+						open_tags.push(name);
+					},
+					Tag::Link(url) if !open_tags.contains(&"a") => {
+						output.push_str("<a href=\"");
+						output.push_str(&escape(url));
+						output.push_str("\">");
+						open_tags.push("a");
+					},
+					Tag::Close(name) if open_tags.contains(&name) => {
+						// Close the tags opened inside this one too
+						while let Some(open) = open_tags.pop() {
+							close(&mut output, open);
+							if open == name {
+								break;
+							}
+						}
+					},
+					_ => output.push_str(&escape(&rest[..length]))
+				}
+
+				previous = Some('>');
+				rest = &rest[length..];
+				continue;
+			}
+		}
+
+		// Bare URLs, outside of the links
+		if !previous.is_some_and(|p| p.is_alphanumeric()) && !open_tags.contains(&"a") {
+			if let Some(length) = url_length(rest) {
+				let url = &rest[..length];
+				output.push_str("<a href=\"");
+				output.push_str(&escape(url));
+				output.push_str("\">[url]</a>");
+
+				previous = url.chars().last();
+				rest = &rest[length..];
+				continue;
+			}
+		}
+
+		escape_char(&mut output, c);
+		previous = Some(c);
+		rest = &rest[c.len_utf8()..];
+	}
+
+	while let Some(open) = open_tags.pop() {
+		close(&mut output, open);
+	}
+	output
+}
+
+
+/// Escapes the HTML special characters of a text.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::core::markup::escape;
+///
+/// assert_eq!(escape("<a href='x'>&</a>"), "&lt;a href=&#39;x&#39;&gt;&amp;&lt;/a&gt;");
+/// ```
+pub fn escape(p_text: &str) -> String {
+	let mut output = String::with_capacity(p_text.len());
+	for c in p_text.chars() {
+		escape_char(&mut output, c);
+	}
+	output
+}
+
+
+/// Appends a character to an HTML text, escaping it if needed.
+fn escape_char(p_output: &mut String, p_char: char) {
+	match p_char {
+		'&'  => p_output.push_str("&amp;"),
+		'<'  => p_output.push_str("&lt;"),
+		'>'  => p_output.push_str("&gt;"),
+		'"'  => p_output.push_str("&quot;"),
+		'\'' => p_output.push_str("&#39;"),
+		_    => p_output.push(p_char)
+	}
+}
+
+
+/// Appends the closing tag of an element.
+fn close(p_output: &mut String, p_name: &str) {
+	p_output.push_str("</");
+	p_output.push_str(p_name);
+	p_output.push('>');
+}
+
+
+/// Tells whether a text is an URL with an allowed scheme.
+fn is_url(p_text: &str) -> bool {
+	URL_SCHEMES.iter().any(|s| p_text.len() > s.len() && starts_with_scheme(p_text, s))
+}
+
+
+/// Tells whether a text starts with an URL scheme, ignoring the case.
+///
+/// The bytes are compared, as the scheme may end inside a multibyte character of the text.
+fn starts_with_scheme(p_text: &str, p_scheme: &str) -> bool {
+	p_text.as_bytes().get(..p_scheme.len()).is_some_and(|b| b.eq_ignore_ascii_case(p_scheme.as_bytes()))
+}
+
+
+/// Gives the length of the URL at the beginning of a text, if there is one.
+///
+/// The URL stops at the first blank, quote or angle bracket. The punctuation
+/// ending a sentence is not part of it.
+fn url_length(p_text: &str) -> Option<usize> {
+	if !URL_SCHEMES.iter().any(|s| starts_with_scheme(p_text, s)) {
+		return None;
+	}
+
+	let end = p_text.find(|c: char| c.is_whitespace() || c == '<' || c == '>' || c == '"' || c == '\'')
+			.unwrap_or(p_text.len());
+	let url = p_text[..end].trim_end_matches(['.', ',', ';', ':', '!', '?', ')']);
+	Some(url.len()).filter(|_| is_url(url))
+}
+
+
+/// A tag of the tribune markup.
+enum Tag<'a> {
+	/// An opening tag without attribute
+	Open(&'static str),
+	/// An opening link tag, with its URL
+	Link(&'a str),
+	/// A closing tag
+	Close(&'static str),
+}
+
+impl<'a> Tag<'a> {
+	/// Parses the tag at the beginning of a text, and gives its length.
+	///
+	/// Only the tags of the tribune markup are recognized.
+	fn parse(p_text: &'a str) -> Option<(Tag<'a>, usize)> {
+		let end = p_text.find('>')?;
+		let inner = &p_text[1..end];
+		let length = end + 1;
+
+		if let Some(name) = inner.strip_prefix('/') {
+			return tag_name(name.trim_end()).map(|n| (Tag::Close(n), length));
+		}
+
+		if let Some(name) = tag_name(inner.trim_end()) {
+			return Some((Tag::Open(name), length));
+		}
+
+		// A link: <a href="url"> or <a href='url'>
+		let attribute = inner.strip_prefix("a ").or_else(|| inner.strip_prefix("A "))?.trim();
+		let value = attribute.get(..5).filter(|h| h.eq_ignore_ascii_case("href="))
+				.map(|_| &attribute[5..])?;
+		let quote = value.chars().next().filter(|q| *q == '"' || *q == '\'')?;
+		let url = value[1..].strip_suffix(quote)?;
+
+		if url.contains(quote) || !is_url(url) {
+			return None;
+		}
+		Some((Tag::Link(url), length))
+	}
+}
+
+
+/// Gives the canonical name of an allowed tag.
+fn tag_name(p_name: &str) -> Option<&'static str> {
+	if p_name.eq_ignore_ascii_case("a") {
+		return Some("a");
+	}
+	SIMPLE_TAGS.iter().find(|t| p_name.eq_ignore_ascii_case(t)).copied()
+}
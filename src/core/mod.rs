@@ -1,9 +1,22 @@
+pub use self::accounts::AccountRegistry;
+pub use self::broadcast::PostBroadcaster;
 pub use self::history::History;
 pub use self::history::LockedHistory;
+pub use self::limiter::RateLimiter;
 pub use self::post::Post;
 pub use self::post::UserPost;
 pub use self::recorder::HistoryRecorder;
+pub use self::registry::BoardRegistry;
+pub use self::search::SearchIndex;
 
+pub mod accounts;
+pub mod broadcast;
 pub mod history;
+pub mod limiter;
+pub mod markup;
+pub mod norloge;
 pub mod post;
 pub mod recorder;
+pub mod registry;
+pub mod search;
+pub mod timezone;
@@ -0,0 +1,384 @@
+//! The norloges, references to other posts by their timestamp.
+//!
+//! A norloge is the clock of a post written in a message to answer it. It
+//! can take several forms:
+//!
+//! * `hh:mm:ss` - the post(s) of that second,
+//! * `hh:mm` - the posts of that minute,
+//! * `YYYY/MM/DD#hh:mm:ss` - the post(s) of that second, on another day.
+//!
+//! When several posts share the same second, a norloge can be disambiguated
+//! with the index of the post in that second, written `^2` or `²`.
+
+use crate::core::History;
+use std::collections::HashMap;
+
+
+/// A timestamp reference found in a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Norloge {
+	/// The day, formatted as "YYYYmmdd"
+	date  : Option<String>,
+	/// The clock, formatted as "HHMM" or "HHMMSS"
+	clock : String,
+	/// The index of the post in its second, starting at 1
+	index : Option<usize>,
+}
+
+impl Norloge {
+	/// Finds the norloges of a message.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::norloge::Norloge;
+	///
+	/// let norloges = Norloge::parse("12:00:42 plop 2016/10/26#08:15:00 13:37 12:00:42^2 12:00:42³");
+	/// assert_eq!(norloges.len(), 5);
+	///
+	/// assert_eq!(norloges[0].date(),  None);
+	/// assert_eq!(norloges[0].clock(), "120042");
+	/// assert_eq!(norloges[0].index(), None);
+	///
+	/// assert_eq!(norloges[1].date(),  Some("20161026"));
+	/// assert_eq!(norloges[1].clock(), "081500");
+	///
+	/// assert_eq!(norloges[2].clock(), "1337");
+	/// assert!(!norloges[2].has_seconds());
+	///
+	/// assert_eq!(norloges[3].index(), Some(2));
+	/// assert_eq!(norloges[4].index(), Some(3));
+	/// ```
+	///
+	/// Numbers that only look like clocks are ignored:
+	///
+	/// ```
+	/// use oxyboard::core::norloge::Norloge;
+	///
+	/// assert!(Norloge::parse("25:00:00 12:61 112:00:00 12:00:000").is_empty());
+	///
+	/// // An index too large is ignored
+	/// let norloges = Norloge::parse(&format!("12:00:42{} 12:00:42^{}", "⁹".repeat(25), "9".repeat(25)));
+	/// assert!(norloges.iter().all(|n| n.index().is_none()));
+	/// ```
+	pub fn parse(p_message: &str) -> Vec<Norloge> {
+		let mut norloges = Vec::new();
+		let mut previous: Option<char> = None;
+		let mut rest = p_message;
+
+		while let Some(c) = rest.chars().next() {
+			// A norloge cannot be glued to a previous number
+			if c.is_ascii_digit() && !matches!(previous, Some(p) if p.is_ascii_digit() || p == ':' || p == '/' || p == '#') {
+				if let Some((norloge, length)) = Scanner::new(rest).norloge() {
+					norloges.push(norloge);
+					previous = rest[..length].chars().last();
+					rest = &rest[length..];
+					continue;
+				}
+			}
+
+			previous = Some(c);
+			rest = &rest[c.len_utf8()..];
+		}
+		norloges
+	}
+
+
+	/// Gives the day of the norloge, formatted as "YYYYmmdd", if it has one.
+	pub fn date(&self) -> Option<&str> {
+		self.date.as_deref()
+	}
+
+
+	/// Gives the clock of the norloge, formatted as "HHMM" or "HHMMSS".
+	pub fn clock(&self) -> &str {
+		&self.clock
+	}
+
+
+	/// Gives the index of the post in its second, if the norloge has one.
+	pub fn index(&self) -> Option<usize> {
+		self.index
+	}
+
+
+	/// Tells whether the norloge references a second or a whole minute.
+	pub fn has_seconds(&self) -> bool {
+		self.clock.len() == 6
+	}
+
+
+	/// Tells whether a post time (formatted as "YYYYmmddHHMMSS") matches the
+	/// norloge, regardless of its index.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::norloge::Norloge;
+	///
+	/// let norloge = &Norloge::parse("12:00")[0];
+	/// assert!(norloge.matches("20161026120042"));
+	/// assert!(!norloge.matches("20161026120142"));
+	/// ```
+	pub fn matches(&self, p_time: &str) -> bool {
+		p_time.len() == 14
+			&& self.date.as_ref().is_none_or(|d| p_time[..8] == *d)
+			&& p_time[8..].starts_with(&self.clock)
+	}
+
+
+	/// Finds the ids of the posts of the history referenced by the norloge.
+	///
+	/// A norloge without a date references the most recent matching second
+	/// (or minute). All the posts of that second are returned, unless the
+	/// norloge has an index, in which case only that post is returned.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::{History, Post, UserPost};
+	/// use oxyboard::core::norloge::Norloge;
+	///
+	/// let mut hist = History::new(&String::from("Oxyboard"), 512);
+	/// for (id, time, index) in [(1, "20161025120042", 1), (2, "20161026120042", 1), (3, "20161026120042", 2)].iter() {
+	///     let msg = UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Plop!"));
+	///     hist.add_full_post(Post::new(*id, String::from(*time), msg).with_index(*index));
+	/// }
+	///
+	/// assert_eq!(Norloge::parse("12:00:42")[0].resolve(&hist),            vec![2, 3]);
+	/// assert_eq!(Norloge::parse("12:00:42²")[0].resolve(&hist),           vec![3]);
+	/// assert_eq!(Norloge::parse("2016/10/25#12:00:42")[0].resolve(&hist), vec![1]);
+	/// assert!(Norloge::parse("12:00:43")[0].resolve(&hist).is_empty());
+	/// ```
+	pub fn resolve(&self, p_history: &History) -> Vec<u64> {
+		// The most recent matching post gives the referenced second (or minute)
+		let prefix_length = 8 + self.clock.len();
+		let prefix = match p_history.iter().rev().find(|p| self.matches(p.time())) {
+			Some(post) => &post.time()[..prefix_length],
+			None       => return Vec::new()
+		};
+
+		p_history.iter()
+			.filter(|p| p.time().starts_with(prefix))
+			.filter(|p| self.index.is_none_or(|i| p.index() as usize == i))
+			.map(|p| p.id())
+			.collect()
+	}
+}
+
+
+/// A `ReplyIndex` keeps track of the posts answering each other.
+///
+/// For each post, it records the posts referenced by its norloges and the
+/// posts which reference it.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::core::norloge::ReplyIndex;
+///
+/// let mut index = ReplyIndex::new();
+/// index.add(3, vec![1, 2]);
+/// index.add(4, vec![1]);
+///
+/// assert_eq!(index.references(3), &[1, 2]);
+/// assert_eq!(index.replies(1),    &[3, 4]);
+///
+/// index.remove(1);
+/// assert!(index.replies(1).is_empty());
+/// assert_eq!(index.references(3), &[1, 2]);
+/// ```
+#[derive(Default)]
+pub struct ReplyIndex {
+	references : HashMap<u64, Vec<u64>>,
+	replies    : HashMap<u64, Vec<u64>>,
+}
+
+impl ReplyIndex {
+	/// Constructs an empty index.
+	pub fn new() -> ReplyIndex {
+		ReplyIndex::default()
+	}
+
+
+	/// Records the posts referenced by a new post.
+	pub fn add(&mut self, p_post_id: u64, p_references: Vec<u64>) {
+		if p_references.is_empty() {
+			return;
+		}
+
+		for referenced in &p_references {
+			self.replies.entry(*referenced).or_default().push(p_post_id);
+		}
+		self.references.insert(p_post_id, p_references);
+	}
+
+
+	/// Forgets the replies to a post and its own references.
+	///
+	/// The references to this post made by other posts are kept.
+	pub fn remove(&mut self, p_post_id: u64) {
+		self.replies.remove(&p_post_id);
+
+		if let Some(references) = self.references.remove(&p_post_id) {
+			for referenced in references {
+				if let Some(replies) = self.replies.get_mut(&referenced) {
+					replies.retain(|id| *id != p_post_id);
+				}
+			}
+		}
+	}
+
+
+	/// Gives the ids of the posts referenced by a post.
+	pub fn references(&self, p_post_id: u64) -> &[u64] {
+		self.references.get(&p_post_id).map_or(&[], |r| r.as_slice())
+	}
+
+
+	/// Gives the ids of the posts referencing a post.
+	pub fn replies(&self, p_post_id: u64) -> &[u64] {
+		self.replies.get(&p_post_id).map_or(&[], |r| r.as_slice())
+	}
+}
+
+
+/// A cursor to scan a norloge at the beginning of a text.
+struct Scanner<'a> {
+	text     : &'a str,
+	position : usize,
+}
+
+impl<'a> Scanner<'a> {
+	fn new(p_text: &'a str) -> Scanner<'a> {
+		Scanner {
+			text     : p_text,
+			position : 0,
+		}
+	}
+
+
+	/// Gives the next character without consuming it.
+	fn peek(&self) -> Option<char> {
+		self.text[self.position..].chars().next()
+	}
+
+
+	/// Consumes the given character.
+	fn literal(&mut self, p_expected: char) -> Option<()> {
+		if self.peek()? != p_expected {
+			return None;
+		}
+		self.position += p_expected.len_utf8();
+		Some(())
+	}
+
+
+	/// Consumes exactly `p_length` ASCII digits and returns them.
+	fn digits(&mut self, p_length: usize) -> Option<&'a str> {
+		let digits = self.text.get(self.position..self.position + p_length)?;
+		if !digits.bytes().all(|b| b.is_ascii_digit()) {
+			return None;
+		}
+		self.position += p_length;
+		Some(digits)
+	}
+
+
+	/// Consumes a two-digit number lower than `p_max`.
+	fn number(&mut self, p_max: u32) -> Option<&'a str> {
+		let digits = self.digits(2)?;
+		if digits.parse::<u32>().ok()? >= p_max {
+			return None;
+		}
+		Some(digits)
+	}
+
+
+	/// Runs a scanning function, restoring the position if it fails.
+	fn attempt<T>(&mut self, p_scan: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+		let start = self.position;
+		let result = p_scan(self);
+		if result.is_none() {
+			self.position = start;
+		}
+		result
+	}
+
+
+	/// Scans a "YYYY/MM/DD#" date.
+	fn date(&mut self) -> Option<String> {
+		let year = self.digits(4)?;
+		self.literal('/')?;
+		let month = self.number(13).filter(|m| *m != "00")?;
+		self.literal('/')?;
+		let day = self.number(32).filter(|d| *d != "00")?;
+		self.literal('#')?;
+		Some(format!("{}{}{}", year, month, day))
+	}
+
+
+	/// Scans an index, either "^n" or superscript digits.
+	///
+	/// An index too large for an `usize` is not an index.
+	fn index(&mut self) -> Option<usize> {
+		if self.literal('^').is_some() {
+			let length = self.text[self.position..].bytes().take_while(|b| b.is_ascii_digit()).count();
+			return self.digits(length).and_then(|d| d.parse().ok()).filter(|i| *i > 0);
+		}
+
+		let mut index: Option<usize> = None;
+		while let Some(digit) = self.peek().and_then(superscript_digit) {
+			index = Some(index.unwrap_or(0).checked_mul(10)?.checked_add(digit)?);
+			self.position += self.peek()?.len_utf8();
+		}
+		index.filter(|i| *i > 0)
+	}
+
+
+	/// Scans a whole norloge and returns it with its length.
+	fn norloge(mut self) -> Option<(Norloge, usize)> {
+		let date = self.attempt(Scanner::date);
+
+		let mut clock = String::from(self.number(24)?);
+		self.literal(':')?;
+		clock.push_str(self.number(60)?);
+		let seconds = self.attempt(|s| {
+			s.literal(':')?;
+			s.number(60)
+		});
+
+		let index = match seconds {
+			Some(seconds) => {
+				clock.push_str(seconds);
+				self.attempt(Scanner::index)
+			},
+			None => None
+		};
+
+		// A norloge cannot be glued to a following number
+		if self.peek().is_some_and(|c| c.is_ascii_digit()) {
+			return None;
+		}
+
+		Some((Norloge { date, clock, index }, self.position))
+	}
+}
+
+
+/// Gives the value of a superscript digit.
+fn superscript_digit(p_char: char) -> Option<usize> {
+	match p_char {
+		'⁰' => Some(0),
+		'¹' => Some(1),
+		'²' => Some(2),
+		'³' => Some(3),
+		'⁴' => Some(4),
+		'⁵' => Some(5),
+		'⁶' => Some(6),
+		'⁷' => Some(7),
+		'⁸' => Some(8),
+		'⁹' => Some(9),
+		_   => None
+	}
+}
@@ -6,6 +6,15 @@
 /// whereas the `Post` represents the final data stored in the history.
 
 
+use crate::core::markup;
+use crate::core::timezone::Timezone;
+use chrono::{DateTime, SecondsFormat, Utc};
+
+
+/// The message replacing the one of a post masked by a moderator.
+pub const MASKED_MESSAGE: &str = "[message masked by a moderator]";
+
+
  /// Contains the data extracted from a post request.
  ///
  /// The `login` field is the account name of the author if the user is
@@ -63,8 +72,15 @@
 /// The `id` field is a unique identifier of the post. It can be used to track responses to a
 /// message.
 ///
-/// The `time` field is a (non-unique) datetime that follows the format "YYYYmmddHHMMSS". It is the official
-/// timestamp of the post. It can also be used to track responses to a message.
+/// The `instant` field is the UTC datetime when the post was added to the history, with a
+/// sub-second precision. It is the official timestamp of the post.
+///
+/// The `time` field is a (non-unique) datetime that follows the format "YYYYmmddHHMMSS". It is the clock
+/// of the `instant` in the timezone of the board, kept for the traditional clients. It can also be used
+/// to track responses to a message.
+///
+/// The `index` field tells the posts of the same second apart: the first post of a second has the
+/// index 1, the next one 2, and so on. It is given by the `History` when the post is added.
 ///
 /// The `login` field is the account name of the author if the user is
 /// authenticated.
@@ -74,25 +90,37 @@
 /// can be modified at will by browser extensions or dedicated clients, it is
 /// easy to set and modify, even if it cannot provide a verified identity.
 ///
-/// The `message` field contains the message content.
+/// The `message` field contains the message content, as sent by the user.
+/// Its normalized form, safe to insert in an HTML page, is kept along (see
+/// the `markup` module). Only the raw message is serialized.
 ///
 /// All these fields are immutable.
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "StoredPost", into = "StoredPost")]
 pub struct Post {
 	/// The post's unique identifier
 	id: u64,
-	/// The datetime when the post was added to the history
+	/// The instant when the post was added to the history
+	instant: DateTime<Utc>,
+	/// The clock of the instant in the timezone of the board
 	time: String,
+	/// The index of the post in its second, starting at 1
+	index: u32,
 	/// The user's login (may be empty)
 	login: String,
 	/// The user's UserAgent header value
 	user_agent: String,
 	/// The message content (may be empty)
 	message: String,
+	/// The normalized message content
+	normalized_message: String,
 }
 
 impl Post {
-	/// Constructs a new `Post`.
+	/// Constructs a new `Post` from its traditional time.
+	///
+	/// The time is taken in the local timezone of the server, as the
+	/// traditional posts were dated.
 	///
 	/// # Examples
 	///
@@ -106,12 +134,44 @@ impl Post {
 	/// let post = Post::new(42, String::from("20161026120000"), request);
 	/// ```
 	pub fn new(p_id: u64, p_datetime: String, p_parser: UserPost) -> Post {
+		let instant = Timezone::Local.instant_of(&p_datetime).unwrap_or(DateTime::UNIX_EPOCH);
+		Post::with_time(p_id, instant, p_datetime, p_parser)
+	}
+
+
+	/// Constructs a new `Post` added at a given instant to a board of the
+	/// given timezone.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use chrono::{TimeZone, Utc};
+	/// use oxyboard::core::{Post, UserPost};
+	/// use oxyboard::core::timezone::Timezone;
+	///
+	/// let instant = Utc.with_ymd_and_hms(2016, 10, 26, 12, 0, 0).unwrap();
+	/// let request = UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Plop!"));
+	/// let post = Post::at(42, instant, &"-03:00".parse::<Timezone>().unwrap(), request);
+	/// assert_eq!(post.instant(), &instant);
+	/// assert_eq!(post.time(), "20161026090000");
+	/// ```
+	pub fn at(p_id: u64, p_instant: DateTime<Utc>, p_timezone: &Timezone, p_parser: UserPost) -> Post {
+		Post::with_time(p_id, p_instant, p_timezone.time_of(&p_instant), p_parser)
+	}
+
+
+	/// Constructs a `Post` from both its instant and its time, as they were
+	/// stored.
+	pub fn with_time(p_id: u64, p_instant: DateTime<Utc>, p_time: String, p_parser: UserPost) -> Post {
 		Post {
-			id         : p_id,
-			time       : p_datetime,
-			login      : p_parser.login,
-			user_agent : p_parser.user_agent,
-			message    : p_parser.message
+			id                 : p_id,
+			instant            : p_instant,
+			time               : p_time,
+			index              : 1,
+			login              : p_parser.login,
+			user_agent         : p_parser.user_agent,
+			normalized_message : markup::normalize(&p_parser.message),
+			message            : p_parser.message
 		}
 	}
 
@@ -133,7 +193,14 @@ impl Post {
 	}
 
 
-	/// Gives the post's creation datetime.
+	/// Gives the post's creation instant.
+	pub fn instant(&self) -> &DateTime<Utc> {
+		&self.instant
+	}
+
+
+	/// Gives the post's creation datetime, as "YYYYmmddHHMMSS" in the timezone
+	/// of its board.
 	///
 	/// # Examples
 	///
@@ -150,6 +217,33 @@ impl Post {
 	}
 
 
+	/// Gives the post's index in its second, starting at 1.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::UserPost;
+	/// use oxyboard::core::Post;
+	///
+	/// let request = UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Plop!"));
+	/// let post = Post::new(42, String::from("20161026120000"), request);
+	/// assert_eq!(post.index(), 1);
+	/// assert_eq!(post.with_index(2).index(), 2);
+	/// ```
+	pub fn index(&self) -> u32 {
+		self.index
+	}
+
+
+	/// Gives the post with another index in its second.
+	pub fn with_index(self, p_index: u32) -> Post {
+		Post {
+			index : p_index,
+			..self
+		}
+	}
+
+
 	/// Gives the post's author login (if it was authenticated).
 	///
 	/// An empty string means the post was send by an anonymous user.
@@ -205,6 +299,24 @@ impl Post {
 	}
 
 
+	/// Gives the post's message with a normalized markup.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::UserPost;
+	/// use oxyboard::core::Post;
+	///
+	/// let request = UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("<b>Plop</b> <blink>!"));
+	/// let post = Post::new(42, String::from("20161026120000"), request);
+	/// assert_eq!(post.message(),            "<b>Plop</b> <blink>!");
+	/// assert_eq!(post.normalized_message(), "<b>Plop</b> &lt;blink&gt;!");
+	/// ```
+	pub fn normalized_message(&self) -> &String {
+		&self.normalized_message
+	}
+
+
 	/// Tells whether the post is from an authenticated user or not
 	///
 	/// # Examples
@@ -233,4 +345,112 @@ impl Post {
     pub fn is_authenticated(&self) -> bool {
 		!self.login.is_empty()
     }
+
+
+	/// Gives the post's author: its login, or its UserAgent if it is anonymous.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::UserPost;
+	/// use oxyboard::core::Post;
+	///
+	/// let msg = UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Plop!"));
+	/// assert_eq!(Post::new(42, String::from("20161026120000"), msg).author(), "Firefox/48.0.1");
+	///
+	/// let msg = UserPost::new(String::from("ptramo"), String::from("Firefox/48.0.1"), String::from("Plop!"));
+	/// assert_eq!(Post::new(43, String::from("20161026120000"), msg).author(), "ptramo");
+	/// ```
+	pub fn author(&self) -> &str {
+		if self.is_authenticated() { &self.login } else { &self.user_agent }
+	}
+
+
+	/// Gives a copy of the post whose time is the clock of its instant in
+	/// another timezone.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use chrono::{TimeZone, Utc};
+	/// use oxyboard::core::{Post, UserPost};
+	/// use oxyboard::core::timezone::Timezone;
+	///
+	/// let instant = Utc.with_ymd_and_hms(2016, 10, 26, 12, 0, 0).unwrap();
+	/// let request = UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Plop!"));
+	/// let post = Post::at(42, instant, &Timezone::Local, request).in_timezone(&"UTC".parse().unwrap());
+	/// assert_eq!(post.time(), "20161026120000");
+	/// ```
+	pub fn in_timezone(&self, p_timezone: &Timezone) -> Post {
+		Post {
+			time : p_timezone.time_of(&self.instant),
+			..self.clone()
+		}
+	}
+
+
+	/// Gives a copy of the post whose message is replaced by `MASKED_MESSAGE`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::core::UserPost;
+	/// use oxyboard::core::post::{Post, MASKED_MESSAGE};
+	///
+	/// let msg = UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Buy cheap stuff!"));
+	/// let post = Post::new(42, String::from("20161026120000"), msg).masked();
+	/// assert_eq!(post.id(), 42);
+	/// assert_eq!(post.message(), MASKED_MESSAGE);
+	/// ```
+	pub fn masked(&self) -> Post {
+		Post {
+			message            : String::from(MASKED_MESSAGE),
+			normalized_message : markup::normalize(MASKED_MESSAGE),
+			..self.clone()
+		}
+	}
+}
+
+
+/// The serialized form of a `Post`, without the normalized message.
+///
+/// The instant, as RFC 3339, and the index come last, so that the posts
+/// stored before they existed can still be read; their time is then taken in
+/// the local timezone, and their index is 1.
+#[derive(Serialize, Deserialize)]
+struct StoredPost {
+	id: u64,
+	time: String,
+	login: String,
+	user_agent: String,
+	message: String,
+	#[serde(default)]
+	instant: Option<String>,
+	#[serde(default)]
+	index: Option<u32>,
+}
+
+impl From<StoredPost> for Post {
+	fn from(p_stored: StoredPost) -> Post {
+		let user_post = UserPost::new(p_stored.login, p_stored.user_agent, p_stored.message);
+		let post = match p_stored.instant.and_then(|i| DateTime::parse_from_rfc3339(&i).ok()) {
+			Some(instant) => Post::with_time(p_stored.id, instant.with_timezone(&Utc), p_stored.time, user_post),
+			None          => Post::new(p_stored.id, p_stored.time, user_post)
+		};
+		post.with_index(p_stored.index.unwrap_or(1))
+	}
+}
+
+impl From<Post> for StoredPost {
+	fn from(p_post: Post) -> StoredPost {
+		StoredPost {
+			id         : p_post.id,
+			time       : p_post.time,
+			login      : p_post.login,
+			user_agent : p_post.user_agent,
+			message    : p_post.message,
+			instant    : Some(p_post.instant.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+			index      : Some(p_post.index),
+		}
+	}
 }
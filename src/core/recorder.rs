@@ -1,33 +1,111 @@
-use crate::core::history::HistoryListener;
+use crate::config::{FsyncPolicy, StorageParams};
+use crate::core::history::{HistoryListener, Moderation};
 use crate::core::post::Post;
 use crate::storage::StorageBackend;
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+
+/// The maximum number of records written together.
+const MAX_BATCH: usize = 1024;
+
+
+/// A change of the history to record.
+enum Record {
+	Post(Post),
+	Moderation(u64, Moderation),
+}
 
 
 /// An history listener that records all new posts using a `StorageBackend`.
-pub struct HistoryRecorder<T> where T: StorageBackend {
-	storage: T,
+///
+/// The posts are not written by the threads adding them to the history: they
+/// are queued to a background writer, which writes the waiting posts
+/// together and flushes them to the disk following the `fsync` policy of the
+/// storage parameters. When the queue is full, the new posts wait for room.
+///
+/// The writer also keeps the stored state of the board up to date, advancing
+/// its post id sequence with the written posts. A storage without state,
+/// because it was never restored by `storage::restore_history()`, is left so.
+///
+/// Dropping the recorder writes the queued posts and flushes them before
+/// returning, so that no post is lost when the server stops. The process
+/// must not exit before, as `std::process::exit()` drops nothing.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::config;
+/// use oxyboard::core::{ History, HistoryRecorder, UserPost };
+/// use oxyboard::storage::{ CsvFileStorage, StorageBackend };
+/// use std::sync::Arc;
+///
+/// let dir = std::env::temp_dir().join(format!("oxyboard-recorder-{}", std::process::id()));
+/// let storage = Arc::new(CsvFileStorage::new(&dir.to_string_lossy().to_string(), String::from("history.csv")));
+///
+/// // A small queue, so that the posts wait for the writer
+/// let mut params = config::default().storage;
+/// params.write_queue = 2;
+///
+/// let mut history = History::new(&String::from("Oxyboard"), 512);
+/// history.add_listener(Box::new(HistoryRecorder::new(Arc::clone(&storage), &params)));
+/// for n in 1..=100 {
+///     history.add_post(UserPost::new(String::new(), String::from("Firefox/48.0.1"), format!("Plop {}", n)));
+/// }
+///
+/// // Dropping the history drops the recorder, which writes the queued posts
+/// drop(history);
+/// let posts = storage.posts_between(1, 100).unwrap();
+/// assert_eq!(posts.len(), 100);
+/// assert_eq!(posts[99].message(), "Plop 100");
+///
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct HistoryRecorder {
+	queue  : Option<SyncSender<Record>>,
+	writer : Option<JoinHandle<()>>,
 }
 
 
-impl<T: StorageBackend> HistoryRecorder<T> {
-	/// Initializes the recorder with a `StorageBackend`.
-	pub fn new(p_storage: T) -> HistoryRecorder<T> {
+impl HistoryRecorder {
+	/// Initializes the recorder with a `StorageBackend`, starting its writer.
+	///
+	/// The size of the queue and the flushes follow the storage parameters.
+	pub fn new<T>(p_storage: T, p_params: &StorageParams) -> HistoryRecorder
+			where T: StorageBackend + Send + 'static {
+		let (queue, records) = sync_channel(p_params.write_queue.max(1));
+		let fsync = p_params.fsync;
+		let interval = Duration::from_secs(p_params.fsync_interval);
+
+		let writer = thread::Builder::new()
+				.name(format!("writer {}", p_storage.location()))
+				.spawn(move || write_records(p_storage, records, fsync, interval))
+				.expect("Failed to start the writer of the storage");
+
 		HistoryRecorder {
-			storage: p_storage,
+			queue  : Some(queue),
+			writer : Some(writer),
+		}
+	}
+
+
+	/// Queues a record for the writer.
+	fn record(&self, p_record: Record) {
+		if let Some(queue) = &self.queue {
+			if queue.send(p_record).is_err() {
+				warn_msg!("Failed to record a change: the writer of the storage has stopped");
+			}
 		}
 	}
 }
 
 
-impl<T: StorageBackend> HistoryListener for HistoryRecorder<T> {
-	/// Save the post using the `StorageBackend`.
+impl HistoryListener for HistoryRecorder {
+	/// Queues the post for the writer.
 	fn post_added(&self, p_post: &Post) {
-		match self.storage.save_post(p_post) {
-			Ok(_)  => {},
-			Err(e) => warn_msg!("Failed to record post #{id}: {err}",
-					id  = p_post.id(),
-					err = e.to_string())
-		}
+		self.record(Record::Post(p_post.clone()));
 	}
 
 
@@ -35,4 +113,136 @@ impl<T: StorageBackend> HistoryListener for HistoryRecorder<T> {
 	fn post_removed(&self, _: &Post) {
 		// Nothing to do
 	}
+
+
+	/// Queues the moderation for the writer.
+	fn post_deleted(&self, p_post: &Post, p_moderation: Moderation) {
+		self.record(Record::Moderation(p_post.id(), p_moderation));
+	}
+}
+
+
+/// Closes the queue, then waits for the writer to write the queued records.
+impl Drop for HistoryRecorder {
+	fn drop(&mut self) {
+		self.queue.take();
+		if let Some(writer) = self.writer.take() {
+			if writer.join().is_err() {
+				warn_msg!("The writer of the storage has failed");
+			}
+		}
+	}
+}
+
+
+/// Writes the queued records until the queue is closed.
+///
+/// The records waiting in the queue are written together. With the
+/// `interval` policy, the writer wakes up to flush the last writes even if
+/// no record comes.
+fn write_records<T: StorageBackend>(p_storage: T, p_records: Receiver<Record>, p_fsync: FsyncPolicy, p_interval: Duration) {
+	let mut last_sync = Instant::now();
+	let mut unsynced = false;
+	let mut state = match p_storage.load_state() {
+		Ok(state) => state,
+		Err(e)    => {
+			warn_msg!("Failed to read the state of '{}', it will not be updated: {}", p_storage.location(), e);
+			None
+		}
+	};
+
+	loop {
+		let first = if unsynced && p_fsync == FsyncPolicy::Interval {
+			match p_records.recv_timeout(p_interval.saturating_sub(last_sync.elapsed())) {
+				Ok(record)                          => Some(record),
+				Err(RecvTimeoutError::Timeout)      => None,
+				Err(RecvTimeoutError::Disconnected) => break
+			}
+		}
+		else {
+			match p_records.recv() {
+				Ok(record) => Some(record),
+				Err(_)     => break
+			}
+		};
+
+		if let Some(record) = first {
+			let mut batch = vec![ record ];
+			batch.extend(p_records.try_iter().take(MAX_BATCH - 1));
+			let last_id = write_batch(&p_storage, batch);
+			if let (Some(state), Some(id)) = (state.as_mut(), last_id) {
+				state.next_post_id = state.next_post_id.max(id + 1);
+				if let Err(e) = p_storage.save_state(state) {
+					warn_msg!("Failed to save the state of '{}': {}", p_storage.location(), e);
+				}
+			}
+			unsynced = true;
+		}
+
+		let due = match p_fsync {
+			FsyncPolicy::Always   => true,
+			FsyncPolicy::Interval => last_sync.elapsed() >= p_interval,
+			FsyncPolicy::Never    => false
+		};
+		if unsynced && due {
+			sync(&p_storage);
+			last_sync = Instant::now();
+			unsynced = false;
+		}
+	}
+
+	// The queue is closed and empty: make the last writes durable
+	if unsynced && p_fsync != FsyncPolicy::Never {
+		sync(&p_storage);
+	}
+}
+
+
+/// Writes records in order, the consecutive posts being saved together.
+///
+/// Returns the id of the last post of the batch, if any.
+fn write_batch<T: StorageBackend>(p_storage: &T, p_batch: Vec<Record>) -> Option<u64> {
+	let mut posts = Vec::new();
+	let mut last_id = None;
+	for record in p_batch {
+		match record {
+			Record::Post(post)                 => {
+				last_id = Some(post.id());
+				posts.push(post);
+			},
+			Record::Moderation(id, moderation) => {
+				save_posts(p_storage, &mut posts);
+				if let Err(e) = p_storage.moderate_post(id, moderation) {
+					warn_msg!("Failed to record the moderation of post #{id}: {err}",
+							id  = id,
+							err = e.to_string());
+				}
+			}
+		}
+	}
+	save_posts(p_storage, &mut posts);
+	last_id
+}
+
+
+/// Saves the pending posts, emptying them.
+fn save_posts<T: StorageBackend>(p_storage: &T, p_posts: &mut Vec<Post>) {
+	if p_posts.is_empty() {
+		return;
+	}
+	if let Err(e) = p_storage.save_posts(p_posts) {
+		warn_msg!("Failed to record posts #{first} to #{last}: {err}",
+				first = p_posts[0].id(),
+				last  = p_posts[p_posts.len() - 1].id(),
+				err   = e.to_string());
+	}
+	p_posts.clear();
+}
+
+
+/// Flushes the storage to the disk.
+fn sync<T: StorageBackend>(p_storage: &T) {
+	if let Err(e) = p_storage.sync() {
+		warn_msg!("Failed to flush the storage '{}': {}", p_storage.location(), e);
+	}
 }
@@ -0,0 +1,180 @@
+//! The registry of the boards hosted by the server.
+
+use crate::core::{History, LockedHistory, PostBroadcaster};
+use crate::core::search::{LockedSearchIndex, SearchIndex, SearchIndexer};
+use crate::storage::SharedStorage;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+
+/// The number of events kept by the broadcaster of a board for its late subscribers.
+const EVENTS_CAPACITY: usize = 256;
+
+
+/// A board of the registry.
+struct Board {
+	history     : LockedHistory,
+	broadcaster : PostBroadcaster,
+	search      : Arc<LockedSearchIndex>,
+	storage     : Option<SharedStorage>,
+}
+
+impl Board {
+	/// Wraps an history, attaching it a new `PostBroadcaster` and a new
+	/// `SearchIndex` containing its current posts.
+	fn new(mut p_history: History) -> Board {
+		let broadcaster = PostBroadcaster::new(EVENTS_CAPACITY);
+		p_history.add_listener(Box::new(broadcaster.clone()));
+
+		let mut index = SearchIndex::new();
+		for post in p_history.iter() {
+			index.add(post);
+		}
+		let search = Arc::new(RwLock::new(index));
+		p_history.add_listener(Box::new(SearchIndexer::new(Arc::clone(&search))));
+
+		Board {
+			history : RwLock::new(p_history),
+			broadcaster,
+			search,
+			storage : None,
+		}
+	}
+}
+
+
+/// A `BoardRegistry` holds the `History` of every board served by the process.
+///
+/// Each board is identified by the id used in its URLs (`/b/<id>/...`).
+/// One of them is the default board, the one served by the unprefixed routes.
+///
+/// Each registered history is given a `PostBroadcaster` so that its new posts
+/// can be streamed to the clients, and a `SearchIndex` of its posts. A board may also be given the
+/// `StorageBackend` recording its posts, to read its archives.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::core::{BoardRegistry, History};
+///
+/// let mut boards = BoardRegistry::new("default", History::new(&String::from("Oxyboard"), 512));
+/// assert!(boards.add_board("dev", History::new(&String::from("Dev board"), 128)));
+/// assert!(!boards.add_board("dev", History::new(&String::from("Other"), 128)));
+///
+/// assert_eq!(boards.len(), 2);
+/// assert_eq!(boards.default_id(), "default");
+/// assert_eq!(*boards.get("dev").unwrap().read().unwrap().board_name(), String::from("Dev board"));
+/// assert!(boards.get("unknown").is_none());
+/// ```
+pub struct BoardRegistry {
+	default_id : String,
+	boards     : BTreeMap<String, Board>,
+}
+
+impl BoardRegistry {
+	/// Constructs a registry containing only the default board.
+	pub fn new(p_default_id: &str, p_default: History) -> BoardRegistry {
+		let mut boards = BTreeMap::new();
+		boards.insert(p_default_id.to_string(), Board::new(p_default));
+
+		BoardRegistry {
+			default_id : p_default_id.to_string(),
+			boards,
+		}
+	}
+
+
+	/// Registers another board.
+	///
+	/// Returns `false` if a board with the same id is already registered, in
+	/// which case the registry is left untouched.
+	pub fn add_board(&mut self, p_id: &str, p_history: History) -> bool {
+		if self.boards.contains_key(p_id) {
+			return false;
+		}
+
+		self.boards.insert(p_id.to_string(), Board::new(p_history));
+		true
+	}
+
+
+	/// Gives the history of the board with the given id, if any.
+	pub fn get(&self, p_id: &str) -> Option<&LockedHistory> {
+		self.boards.get(p_id).map(|b| &b.history)
+	}
+
+
+	/// Gives the broadcaster of the board with the given id, if any.
+	pub fn broadcaster(&self, p_id: &str) -> Option<&PostBroadcaster> {
+		self.boards.get(p_id).map(|b| &b.broadcaster)
+	}
+
+
+	/// Gives the search index of the board with the given id, if any.
+	pub fn search(&self, p_id: &str) -> Option<&LockedSearchIndex> {
+		self.boards.get(p_id).map(|b| &*b.search)
+	}
+
+
+	/// Sets the storage backend of the board with the given id.
+	///
+	/// Returns `false` if there is no such board.
+	pub fn set_storage(&mut self, p_id: &str, p_storage: SharedStorage) -> bool {
+		match self.boards.get_mut(p_id) {
+			Some(board) => {
+				board.storage = Some(p_storage);
+				true
+			},
+			None => false
+		}
+	}
+
+
+	/// Gives the storage backend of the board with the given id, if any.
+	pub fn storage(&self, p_id: &str) -> Option<&SharedStorage> {
+		self.boards.get(p_id).and_then(|b| b.storage.as_ref())
+	}
+
+
+	/// Gives the history of the default board.
+	pub fn default_board(&self) -> &LockedHistory {
+		&self.boards[&self.default_id].history
+	}
+
+
+	/// Gives the broadcaster of the default board.
+	pub fn default_broadcaster(&self) -> &PostBroadcaster {
+		&self.boards[&self.default_id].broadcaster
+	}
+
+
+	/// Gives the storage backend of the default board, if it has one.
+	pub fn default_storage(&self) -> Option<&SharedStorage> {
+		self.boards[&self.default_id].storage.as_ref()
+	}
+
+
+	/// Gives the id of the default board.
+	pub fn default_id(&self) -> &str {
+		&self.default_id
+	}
+
+
+	/// Returns an iterator on the ids of the registered boards, in alphabetical order.
+	pub fn ids(&self) -> impl Iterator<Item = &String> {
+		self.boards.keys()
+	}
+
+
+	/// Gives the number of registered boards.
+	pub fn len(&self) -> usize {
+		self.boards.len()
+	}
+
+
+	/// Tells whether the registry is empty, which never happens as it always
+	/// contains the default board.
+	pub fn is_empty(&self) -> bool {
+		self.boards.is_empty()
+	}
+}
@@ -0,0 +1,234 @@
+//! The full-text search over the posts of a board.
+
+use crate::core::history::{HistoryListener, Moderation};
+use crate::core::post::Post;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+
+
+/// The criteria of a search.
+///
+/// Every word of `text` must be found in the message, the login or the user
+/// agent of a post. The other criteria are optional filters.
+pub struct SearchQuery {
+	/// The words to look for
+	pub text   : String,
+	/// The login, or the user agent for anonymous posts, of the author
+	pub author : Option<String>,
+	/// The first day of the posts, formatted as "YYYYmmdd"
+	pub from   : Option<String>,
+	/// The last day of the posts, formatted as "YYYYmmdd"
+	pub to     : Option<String>,
+	/// The maximum number of results
+	pub limit  : usize,
+}
+
+impl SearchQuery {
+	/// Constructs a query looking for some words, without any filter.
+	pub fn new(p_text: &str, p_limit: usize) -> SearchQuery {
+		SearchQuery {
+			text   : p_text.to_string(),
+			author : None,
+			from   : None,
+			to     : None,
+			limit  : p_limit,
+		}
+	}
+
+
+	/// Tells whether a day, as indexed, is between the days of the query.
+	fn accepts_day(&self, p_day: u32) -> bool {
+		self.from.as_deref().is_none_or(|f| p_day >= day_number(f))
+			&& self.to.as_deref().is_none_or(|t| p_day <= day_number(t))
+	}
+}
+
+
+/// A `SearchIndex` is an inverted index of the words of the posts.
+///
+/// The message, the login and the user agent of each post are split into
+/// lowercase words. The index only keeps the ids of the posts, with their day
+/// and their author for the filters: the matching posts are then read from the
+/// `History` or from the storage of the board.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::core::{Post, UserPost};
+/// use oxyboard::core::search::{SearchIndex, SearchQuery};
+///
+/// let mut index = SearchIndex::new();
+/// index.add(&Post::new(1, String::from("20161025120000"), UserPost::new(String::from("bob"),   String::from("Firefox/48.0.1"), String::from("Look at https://example.com/plop"))));
+/// index.add(&Post::new(2, String::from("20161026120000"), UserPost::new(String::from("alice"), String::from("Firefox/48.0.1"), String::from("Plop!"))));
+///
+/// assert_eq!(index.search(&SearchQuery::new("plop", 10)), vec![2, 1]);
+/// assert_eq!(index.search(&SearchQuery::new("example.com", 10)), vec![1]);
+///
+/// let mut by_bob = SearchQuery::new("plop", 10);
+/// by_bob.author = Some(String::from("Bob"));
+/// assert_eq!(index.search(&by_bob), vec![1]);
+///
+/// let mut since_26th = SearchQuery::new("firefox", 10);
+/// since_26th.from = Some(String::from("20161026"));
+/// assert_eq!(index.search(&since_26th), vec![2]);
+///
+/// index.remove(1);
+/// assert!(index.search(&SearchQuery::new("example", 10)).is_empty());
+/// assert_eq!(index.len(), 1);
+/// ```
+#[derive(Default)]
+pub struct SearchIndex {
+	days    : BTreeMap<u64, u32>,
+	authors : HashMap<String, BTreeSet<u64>>,
+	words   : HashMap<String, BTreeSet<u64>>,
+}
+
+impl SearchIndex {
+	/// Constructs an empty index.
+	pub fn new() -> SearchIndex {
+		SearchIndex::default()
+	}
+
+
+	/// Indexes a post. A post already indexed is left untouched.
+	pub fn add(&mut self, p_post: &Post) {
+		if self.days.contains_key(&p_post.id()) {
+			return;
+		}
+
+		for field in [p_post.message(), p_post.login(), p_post.user_agent()] {
+			for word in words(field) {
+				self.words.entry(word).or_default().insert(p_post.id());
+			}
+		}
+		self.authors.entry(p_post.author().to_ascii_lowercase()).or_default().insert(p_post.id());
+		self.days.insert(p_post.id(), day_number(p_post.time()));
+	}
+
+
+	/// Forgets a post.
+	///
+	/// As the words of the post are not kept, every posting is scanned: this
+	/// is only meant for the moderations, which are rare.
+	pub fn remove(&mut self, p_post_id: u64) {
+		if self.days.remove(&p_post_id).is_some() {
+			for postings in [&mut self.words, &mut self.authors] {
+				postings.retain(|_, ids| {
+					ids.remove(&p_post_id);
+					!ids.is_empty()
+				});
+			}
+		}
+	}
+
+
+	/// Applies a moderation: a deleted post is forgotten, a masked one is
+	/// indexed again with its masked message.
+	pub fn moderate(&mut self, p_post: &Post, p_moderation: Moderation) {
+		self.remove(p_post.id());
+		if p_moderation == Moderation::Mask {
+			self.add(p_post);
+		}
+	}
+
+
+	/// Gives the number of indexed posts.
+	pub fn len(&self) -> usize {
+		self.days.len()
+	}
+
+
+	/// Tells whether no post has been indexed.
+	pub fn is_empty(&self) -> bool {
+		self.days.is_empty()
+	}
+
+
+	/// Finds the ids of the posts matching a query, the most recent first.
+	///
+	/// A query without any word matches every post passing its filters.
+	pub fn search(&self, p_query: &SearchQuery) -> Vec<u64> {
+		let author = p_query.author.as_ref().map(|a| a.trim().to_ascii_lowercase());
+		let postings = words(&p_query.text).map(|w| self.words.get(&w))
+				.chain(author.map(|a| self.authors.get(&a)));
+
+		let mut candidates: Option<BTreeSet<u64>> = None;
+		for ids in postings {
+			let ids = match ids {
+				Some(ids) => ids,
+				None      => return Vec::new()
+			};
+
+			candidates = Some(match candidates {
+				Some(c) => c.intersection(ids).copied().collect(),
+				None    => ids.clone()
+			});
+		}
+
+		match candidates {
+			Some(ids) => ids.iter().rev()
+				.filter(|id| self.days.get(id).is_some_and(|d| p_query.accepts_day(*d)))
+				.take(p_query.limit)
+				.copied()
+				.collect(),
+			None => self.days.iter().rev()
+				.filter(|(_, d)| p_query.accepts_day(**d))
+				.take(p_query.limit)
+				.map(|(id, _)| *id)
+				.collect()
+		}
+	}
+}
+
+
+pub type LockedSearchIndex = RwLock<SearchIndex>;
+
+
+/// An history listener that indexes the new posts in a shared `SearchIndex`.
+pub struct SearchIndexer {
+	index : Arc<LockedSearchIndex>,
+}
+
+impl SearchIndexer {
+	/// Initializes the indexer with the index to feed.
+	pub fn new(p_index: Arc<LockedSearchIndex>) -> SearchIndexer {
+		SearchIndexer {
+			index : p_index,
+		}
+	}
+}
+
+
+impl HistoryListener for SearchIndexer {
+	/// Indexes the post.
+	fn post_added(&self, p_post: &Post) {
+		self.index.write().unwrap().add(p_post);
+	}
+
+
+	/// Does nothing, the removed posts can still be found in the storage.
+	fn post_removed(&self, _: &Post) {
+		// Nothing to do
+	}
+
+
+	/// Updates the index of the moderated post.
+	fn post_deleted(&self, p_post: &Post, p_moderation: Moderation) {
+		self.index.write().unwrap().moderate(p_post, p_moderation);
+	}
+}
+
+
+/// Converts the "YYYYmmdd" day starting a time into a number, so that the
+/// days are compared like their text.
+fn day_number(p_time: &str) -> u32 {
+	p_time.get(..8).and_then(|d| d.parse().ok()).unwrap_or(0)
+}
+
+
+/// Splits a text into lowercase words.
+fn words(p_text: &str) -> impl Iterator<Item = String> + '_ {
+	p_text.split(|c: char| !c.is_alphanumeric())
+		.filter(|w| !w.is_empty())
+		.map(str::to_lowercase)
+}
@@ -0,0 +1,97 @@
+//! The timezones the posts are displayed in.
+//!
+//! The posts are dated by an UTC instant. Their traditional `time`, the
+//! "YYYYmmddHHMMSS" string used by the backends and the norloges, is the
+//! clock of that instant in the timezone of the board.
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+
+/// The format of the traditional time of the posts.
+pub const TIME_FORMAT: &str = "%Y%m%d%H%M%S";
+
+
+/// The timezone of a board.
+///
+/// # Examples
+///
+/// ```
+/// use chrono::{TimeZone, Utc};
+/// use oxyboard::core::timezone::Timezone;
+///
+/// let instant = Utc.with_ymd_and_hms(2016, 10, 26, 12, 0, 0).unwrap();
+///
+/// let paris: Timezone = "+02:00".parse().unwrap();
+/// assert_eq!(paris.time_of(&instant), "20161026140000");
+/// assert_eq!(paris.instant_of("20161026140000"), Some(instant));
+///
+/// let utc: Timezone = "UTC".parse().unwrap();
+/// assert_eq!(utc.time_of(&instant), "20161026120000");
+///
+/// assert!("Mars/Olympus".parse::<Timezone>().is_err());
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Timezone {
+	/// The local timezone of the server, daylight saving time included
+	#[default]
+	Local,
+	/// A fixed offset from UTC
+	Fixed(FixedOffset),
+}
+
+impl Timezone {
+	/// Gives the traditional time of an instant, in this timezone.
+	pub fn time_of(&self, p_instant: &DateTime<Utc>) -> String {
+		match self {
+			Timezone::Local         => p_instant.with_timezone(&Local).format(TIME_FORMAT).to_string(),
+			Timezone::Fixed(offset) => p_instant.with_timezone(offset).format(TIME_FORMAT).to_string()
+		}
+	}
+
+
+	/// Gives the instant of a traditional time, in this timezone.
+	///
+	/// Returns `None` if the time is invalid. When the clock of the time is
+	/// ambiguous (at the end of the daylight saving time), the earliest
+	/// instant is given.
+	pub fn instant_of(&self, p_time: &str) -> Option<DateTime<Utc>> {
+		let time = NaiveDateTime::parse_from_str(p_time, TIME_FORMAT).ok()?;
+		match self {
+			Timezone::Local         => Local.from_local_datetime(&time).earliest().map(|t| t.with_timezone(&Utc)),
+			Timezone::Fixed(offset) => offset.from_local_datetime(&time).earliest().map(|t| t.with_timezone(&Utc))
+		}
+	}
+}
+
+
+/// Parses `local`, `UTC` (or `Z`), or an offset like `+02:00`.
+impl FromStr for Timezone {
+	type Err = String;
+
+	fn from_str(p_text: &str) -> Result<Timezone, String> {
+		let text = p_text.trim();
+		if text.eq_ignore_ascii_case("local") {
+			return Ok(Timezone::Local);
+		}
+		if text.eq_ignore_ascii_case("utc") || text == "Z" {
+			return Ok(Timezone::Fixed(FixedOffset::east_opt(0).unwrap()));
+		}
+
+		// Parse the offset as the one of a datetime
+		DateTime::parse_from_rfc3339(&format!("2000-01-01T00:00:00{}", text))
+				.map(|d| Timezone::Fixed(*d.offset()))
+				.map_err(|_| format!("Invalid timezone '{}': expected 'local', 'UTC' or an offset like '+02:00'", text))
+	}
+}
+
+
+impl fmt::Display for Timezone {
+	fn fmt(&self, p_formatter: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Timezone::Local         => write!(p_formatter, "local"),
+			Timezone::Fixed(offset) => write!(p_formatter, "{}", offset)
+		}
+	}
+}
@@ -62,6 +62,7 @@ macro_rules! warn_msg {
 }
 
 
+pub mod admin;
 pub mod config;
 pub mod core;
 pub mod requests;
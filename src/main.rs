@@ -2,84 +2,503 @@ extern crate clap;
 #[macro_use] extern crate rocket;
 extern crate oxyboard;
 
-use clap::{ Arg, App };
+use chrono::NaiveDate;
+use clap::{ Arg, ArgMatches, App, SubCommand };
 use oxyboard::{ info_msg, warn_msg };
-use oxyboard::config;
-use oxyboard::config::{ Config, ConfigLoader, TomlConfigLoader };
-use oxyboard::core::{ History, HistoryRecorder };
-use oxyboard::requests::{ about, backend, board, clients_config, index, post };
-use oxyboard::storage::{ StorageBackend, CsvFileStorage };
+use oxyboard::admin;
+use oxyboard::admin::DumpFormat;
+use oxyboard::config::{ BoardParams, Config, ConfigLoader, LayeredConfigLoader, LimitsParams, StorageParams, TomlConfigLoader };
+use oxyboard::core::{ AccountRegistry, BoardRegistry, History, HistoryRecorder, RateLimiter };
+use oxyboard::core::limiter::{ LimitKind, Rate };
+use oxyboard::requests::{ about, account, archive, backend, board, clients_config, events, feed, index, moderation, post, search, thread, websocket };
+use oxyboard::storage;
+use oxyboard::storage::{ DataLock, SharedStorage, StorageBackend };
+use rocket::{ Build, Rocket };
+use rocket::figment::{ Figment, Profile };
+use rocket::figment::providers::{ Env, Format, Toml };
 use rocket::fs::{ relative, FileServer };
 use rocket_dyn_templates::Template;
+use std::env;
+use std::fs::File;
 use std::io;
-use std::io::Error;
 use std::path::Path;
-use std::sync::RwLock;
+use std::process;
+use std::sync::Arc;
 
 
-/// Loads the configuration from the given file.
+/// Loads the configuration from the given file, overridden by the
+/// `OXYBOARD_*` environment variables and the `--set` arguments.
 ///
-/// If the configuration cannot be loaded, the default configuration from
-/// `oxyboard::config::default()` is returned.
-fn load_config(p_file: &str) -> Config {
-	TomlConfigLoader::new(String::from(p_file)).load()
-			.and_then(|c: Config| {
-				info_msg!("Configuration read from '{}'", p_file);
-				Ok(c)
-			})
-			.or_else(|e: Error| -> io::Result<Config> {
-				warn_msg!("Failed to read the configuration from '{}': {}", p_file, e);
-				info_msg!("Using default hardcoded configuration instead.");
-				Ok(config::default())
-			}).unwrap()
-}
-
-
-#[launch]
-fn rocket() -> _ {
-	let matches = App::new("Oxyboard")
-	    	.version("0.2.0")
-	        .author("Olivier Serve <tifauv@gmail.com>")
-	        .about("A board server written in Rust.")
-	        .arg(Arg::with_name("config")
-	            .short("c")
-	            .long("config")
-	            .value_name("FILE")
-	            .help("Sets a custom config file")
-	            .takes_value(true))
-	      	.get_matches();
+/// If the file does not exist, the default configuration from
+/// `oxyboard::config::default()` is overridden instead. The process exits if
+/// the file or an override is invalid.
+fn load_config(p_file: &str, p_overrides: &[&str]) -> Config {
+	let loader = LayeredConfigLoader::new(TomlConfigLoader::new(String::from(p_file)))
+			.env(env::vars())
+			.overrides(p_overrides);
 
-	// Gets a value for config if supplied by user, or defaults to "config/oxyboard.toml"
-	let config_file = matches.value_of("config").unwrap_or("config/Oxyboard.toml");
-	let config = load_config(&config_file);
+	match loader.load() {
+		Ok(c)  => c,
+		Err(e) => {
+			warn_msg!("Failed to read the configuration from '{}': {}", p_file, e);
+			process::exit(1);
+		}
+	}
+}
 
+
+/// Tells where the configuration comes from.
+fn log_config_source(p_file: &str) {
+	if Path::new(p_file).exists() {
+		info_msg!("Configuration read from '{}'", p_file);
+	}
+	else {
+		info_msg!("No configuration file '{}', using the default configuration.", p_file);
+	}
+}
+
+
+/// Gives the data directory of a board: the root data directory for the
+/// default board, a sub-directory named after its id for the other ones.
+fn board_data_dir(p_config: &Config, p_board: &BoardParams) -> String {
+	if p_board.id == p_config.board.id {
+		p_config.storage.data_dir.clone()
+	}
+	else {
+		format!("{}/{}", p_config.storage.data_dir, p_board.id)
+	}
+}
+
+
+/// Takes the lock of the data directory of a board, so that the server and
+/// the administration tasks do not use its files at the same time.
+///
+/// Exits the process if the lock is held by another process.
+fn lock_board(p_board: &BoardParams, p_data_dir: &str) -> DataLock {
+	match DataLock::acquire(p_data_dir) {
+		Ok(lock) => lock,
+		Err(e)   => {
+			warn_msg!("Failed to lock the data directory of board '{}': {}", p_board.id, e);
+			process::exit(1);
+		}
+	}
+}
+
+
+/// Creates the history of a board and loads its stored posts.
+///
+/// The history is stored in the `p_data_dir` directory, using the configured
+/// storage backend, where its new posts will be recorded.
+///
+/// The storage is returned along with the history, so that the archives of
+/// the board can be read from it.
+///
+/// Exits the process if the storage cannot be opened.
+fn load_board(p_board: &BoardParams, p_storage: &StorageParams, p_data_dir: &str) -> (History, SharedStorage) {
 	// Create the history storage engine
-	let history_storage = CsvFileStorage::new(&config.storage.data_dir, String::from("history.csv"));
+	let history_storage: SharedStorage = match storage::open(p_storage, p_data_dir) {
+		Ok(s)  => Arc::from(s),
+		Err(e) => {
+			warn_msg!("Failed to open the storage of board '{}' in '{}': {}", p_board.id, p_data_dir, e);
+			process::exit(1);
+		}
+	};
 
 	// Create the history
-	let mut history = History::new(&config.board.name, config.board.history_size);
+	let mut history = History::new(&p_board.name, p_board.history_size);
+	match p_board.timezone.parse() {
+		Ok(timezone) => history.set_timezone(timezone),
+		Err(e)       => {
+			warn_msg!("Failed to configure board '{}': {}", p_board.id, e);
+			process::exit(1);
+		}
+	}
+
+	history.set_max_message_length(p_board.max_message_length);
+
+	// Repair the damages of a crash before reading the data
+	match history_storage.repair() {
+		Ok(report) if !report.is_clean() => warn_msg!("Storage '{}' repaired: {}.", history_storage.location(), report),
+		Ok(_)                            => {},
+		Err(e)                           => warn_msg!("Failed to repair the storage '{}': {}", history_storage.location(), e)
+	}
 
 	// Load the history data if any
-	if Path::new(&history_storage.file_path()).exists() {
-		match history_storage.load_history(&mut history) {
-			Ok(n)  => info_msg!("{} posts loaded from history file '{}'.", n, &history_storage.file_path()),
-			Err(e) => warn_msg!("Failed to load the history file '{}': {}", &history_storage.file_path(), e)
-		}
+	match storage::restore_history(&history_storage, &mut history) {
+		Ok(n)  => info_msg!("{} posts loaded from history '{}'.", n, history_storage.location()),
+		Err(e) => warn_msg!("Failed to load the history '{}': {}", history_storage.location(), e)
 	}
 
 	// Add the listeners
-	let history_recorder = HistoryRecorder::new(history_storage);
+	let history_recorder = HistoryRecorder::new(Arc::clone(&history_storage), p_storage);
 	history.add_listener(Box::new(history_recorder));
+	(history, history_storage)
+}
+
+
+/// Indexes all the stored posts of a board for the search, including the
+/// ones that are no longer in its history.
+///
+/// The posts are read one at a time, only their words are kept.
+fn index_archives(p_boards: &BoardRegistry, p_id: &str) {
+	if let (Some(storage), Some(search)) = (p_boards.storage(p_id), p_boards.search(p_id)) {
+		let timezone = *p_boards.get(p_id).unwrap().read().unwrap().timezone();
+		let mut index = search.write().unwrap();
+		match storage.for_each_post(&mut |post| index.add(&post.in_timezone(&timezone))) {
+			Ok(()) => info_msg!("{} posts indexed for the search of board '{}'.", index.len(), p_id),
+			Err(e) => warn_msg!("Failed to index the archives of board '{}': {}", p_id, e)
+		}
+	}
+}
+
+
+/// Builds the rate limiter of the posts and of the accounts from its configuration.
+///
+/// The rates with a null burst are not limited.
+fn rate_limiter(p_limits: &LimitsParams) -> RateLimiter {
+	let rates = [
+		(LimitKind::Ip,        p_limits.ip),
+		(LimitKind::Login,     p_limits.login),
+		(LimitKind::UserAgent, p_limits.user_agent),
+		(LimitKind::Account,   p_limits.account),
+	];
+
+	let mut limiter = RateLimiter::new();
+	for (kind, rate) in rates.iter().filter(|(_, r)| r.burst > 0) {
+		info_msg!("Limited by {:?} to {} at once and {} per minute.", kind, rate.burst, rate.per_minute);
+		limiter = limiter.limit(*kind, Rate { burst: rate.burst, per_minute: rate.per_minute });
+	}
+	limiter
+}
+
+
+/// Opens the storage of the board selected by the `--board` argument of an
+/// administration task, the default board otherwise.
+///
+/// The lock of the data directory of the board is returned along with the
+/// storage: it must be kept while the storage is used.
+///
+/// Exits the process if the board is unknown, if its data directory is locked
+/// by the server or another task, or if its storage cannot be opened.
+fn open_board_storage(p_config: &Config, p_args: &ArgMatches) -> (DataLock, Box<dyn StorageBackend + Send + Sync>) {
+	let id = p_args.value_of("board").unwrap_or(&p_config.board.id);
+	let board = match std::iter::once(&p_config.board).chain(p_config.boards.iter()).find(|b| b.id == id) {
+		Some(b) => b,
+		None    => {
+			warn_msg!("There is no board '{}' in the configuration.", id);
+			process::exit(1);
+		}
+	};
+
+	let data_dir = board_data_dir(p_config, board);
+	let lock = lock_board(board, &data_dir);
+	match storage::open(&p_config.storage, &data_dir) {
+		Ok(s)  => (lock, s),
+		Err(e) => {
+			warn_msg!("Failed to open the storage of board '{}' in '{}': {}", board.id, data_dir, e);
+			process::exit(1);
+		}
+	}
+}
+
+
+/// Gives the format selected by the `--format` argument of an administration task.
+fn dump_format(p_args: &ArgMatches) -> DumpFormat {
+	p_args.value_of("format").unwrap_or("json").parse().unwrap_or_else(|e| {
+		warn_msg!("{}", e);
+		process::exit(1);
+	})
+}
+
+
+/// Writes the posts of a board to a file, or to the standard output.
+fn export(p_config: &Config, p_args: &ArgMatches) {
+	let (_lock, storage) = open_board_storage(p_config, p_args);
+	let format = dump_format(p_args);
+
+	let result = match p_args.value_of("output") {
+		Some(file) => File::create(file).and_then(|f| admin::export_posts(&storage, format, io::BufWriter::new(f)))
+				.map(|n| info_msg!("{} posts exported from '{}' to '{}'.", n, storage.location(), file)),
+		None       => admin::export_posts(&storage, format, io::stdout().lock()).map(|_| ())
+	};
+	if let Err(e) = result {
+		warn_msg!("Failed to export the posts of '{}': {}", storage.location(), e);
+		process::exit(1);
+	}
+}
+
+
+/// Saves the posts of a file in the storage of a board.
+fn import(p_config: &Config, p_args: &ArgMatches) {
+	let (_lock, storage) = open_board_storage(p_config, p_args);
+	let format = dump_format(p_args);
+	let file = p_args.value_of("FILE").unwrap();
+
+	match File::open(file).and_then(|f| admin::import_posts(&storage, format, io::BufReader::new(f))) {
+		Ok((imported, skipped)) => {
+			info_msg!("{} posts imported from '{}' to '{}'.", imported, file, storage.location());
+			if skipped > 0 {
+				warn_msg!("{} posts skipped, their ids being already given.", skipped);
+			}
+		},
+		Err(e) => {
+			warn_msg!("Failed to import the posts of '{}': {}", file, e);
+			process::exit(1);
+		}
+	}
+}
+
+
+/// Removes the posts of a board older than a day.
+fn prune(p_config: &Config, p_args: &ArgMatches) {
+	let before = p_args.value_of("before").unwrap();
+	let day = match NaiveDate::parse_from_str(before, "%Y-%m-%d") {
+		Ok(d)  => d.format("%Y%m%d").to_string(),
+		Err(_) => {
+			warn_msg!("Invalid date '{}': expected YYYY-mm-dd", before);
+			process::exit(1);
+		}
+	};
+
+	let (_lock, storage) = open_board_storage(p_config, p_args);
+	match storage.prune_before(&day) {
+		Ok(n)  => info_msg!("{} posts older than {} removed from '{}'.", n, before, storage.location()),
+		Err(e) => {
+			warn_msg!("Failed to prune '{}': {}", storage.location(), e);
+			process::exit(1);
+		}
+	}
+}
+
+
+/// Removes the deleted posts from the files of a board.
+fn compact(p_config: &Config, p_args: &ArgMatches) {
+	let (_lock, storage) = open_board_storage(p_config, p_args);
+	match storage.compact() {
+		Ok(n)  => info_msg!("{} deleted posts removed from '{}'.", n, storage.location()),
+		Err(e) => {
+			warn_msg!("Failed to compact '{}': {}", storage.location(), e);
+			process::exit(1);
+		}
+	}
+}
+
+
+/// Exits with an error status if the configuration is invalid, telling its problems.
+fn exit_if_invalid(p_config: &Config) {
+	let problems = admin::check_config(p_config);
+	for problem in &problems {
+		warn_msg!("{}", problem);
+	}
+
+	if !problems.is_empty() {
+		process::exit(1);
+	}
+}
+
+
+/// Checks the configuration file, exiting with an error status if it is invalid.
+fn check_config(p_file: &str, p_config: &Config) {
+	log_config_source(p_file);
+	exit_if_invalid(p_config);
+	info_msg!("Configuration '{}' is valid, with {} board(s).", p_file, p_config.boards.len() + 1);
+}
+
+
+/// Gives the configuration of Rocket, read like by `rocket::build()`.
+///
+/// The client IP address is not taken from the `X-Real-IP` header by default,
+/// as any client could then forge it to get around the rate limit. Behind a
+/// trusted reverse proxy setting it, `ROCKET_IP_HEADER=X-Real-IP` restores it.
+fn rocket_figment() -> Figment {
+	Figment::from(rocket::Config::default())
+			.merge(("ip_header", false))
+			.merge(Toml::file(Env::var_or("ROCKET_CONFIG", "Rocket.toml")).nested())
+			.merge(Env::prefixed("ROCKET_").ignore(&["PROFILE"]).global())
+			.select(Profile::from_env_or("ROCKET_PROFILE", rocket::Config::DEFAULT_PROFILE))
+}
+
+
+/// Takes the locks of the data directories of all the boards of a
+/// configuration, for the server.
+fn lock_boards(p_config: &Config) -> Vec<DataLock> {
+	std::iter::once(&p_config.board).chain(p_config.boards.iter())
+			.map(|board| lock_board(board, &board_data_dir(p_config, board)))
+			.collect()
+}
+
+
+/// Builds the server of the boards of a configuration.
+///
+/// The configuration must have been checked by `admin::check_config()`: the
+/// ids of the boards name their data directories.
+fn rocket(p_config: Config) -> Rocket<Build> {
+	// Create the default board, stored at the root of the data directory
+	let (history, history_storage) = load_board(&p_config.board, &p_config.storage, &p_config.storage.data_dir);
+	let mut boards = BoardRegistry::new(&p_config.board.id, history);
+	boards.set_storage(&p_config.board.id, history_storage);
+
+	// Create the other boards, each one in its own data sub-directory
+	for board in &p_config.boards {
+		let (history, history_storage) = load_board(board, &p_config.storage, &board_data_dir(&p_config, board));
+		boards.add_board(&board.id, history);
+		boards.set_storage(&board.id, history_storage);
+	}
+	for id in boards.ids() {
+		index_archives(&boards, id);
+	}
+	// Load the user accounts, stored at the root of the data directory
+	let accounts = match AccountRegistry::load(&p_config.storage.data_dir, "accounts.csv") {
+		Ok(a)  => a,
+		Err(e) => {
+			warn_msg!("Failed to load the accounts from '{}': {}", p_config.storage.data_dir, e);
+			process::exit(1);
+		}
+	};
+	info_msg!("{} user account(s) loaded.", accounts.len());
+
+	info_msg!("Serving {} board(s), '{}' being the default one.", boards.len(), boards.default_id());
+
+	let clients = clients_config::ClientsConfig::new(&p_config);
 
 	// Start the server
-    rocket::build()
+    rocket::custom(rocket_figment())
         .attach(Template::fairing())
-        .manage(RwLock::new(history))
+        .manage(boards)
+        .manage(clients)
+        .manage(p_config.moderation)
+        .manage(accounts)
+        .manage(rate_limiter(&p_config.limits))
         .mount("/", routes![index::redirect])
         .mount("/", routes![about::html])
-        .mount("/", routes![board::html])
-        .mount("/", routes![backend::full_xml, backend::last_xml, backend::since_xml])
-        .mount("/", routes![post::form])
-        .mount("/", routes![clients_config::html])
+        .mount("/", routes![board::html, board::board_html])
+        .mount("/", routes![backend::full, backend::full_json, backend::last, backend::since])
+        .mount("/", routes![backend::board_full, backend::board_full_json, backend::board_last, backend::board_since])
+        .mount("/", routes![archive::day, archive::range, archive::board_day, archive::board_range])
+        .mount("/", routes![search::posts, search::board_posts])
+        .mount("/", routes![feed::atom, feed::rss, feed::board_atom, feed::board_rss])
+        .mount("/", routes![post::form, post::board_form])
+        .mount("/", routes![account::register, account::login, account::logout, account::session])
+        .mount("/", routes![moderation::delete, moderation::mask, moderation::board_delete, moderation::board_mask])
+        .mount("/", routes![thread::replies, thread::references, thread::board_replies, thread::board_references])
+        .mount("/", routes![events::posts, events::board_posts])
+        .mount("/", routes![websocket::connect, websocket::board_connect])
+        .mount("/", routes![clients_config::html, clients_config::xml, clients_config::json])
         .mount("/res", FileServer::from(relative!("static")))
 }
+
+
+fn main() {
+	let config_arg = Arg::with_name("config")
+	        .short("c")
+	        .long("config")
+	        .value_name("FILE")
+	        .help("Sets a custom config file")
+	        .takes_value(true)
+	        .global(true);
+	let set_arg = Arg::with_name("set")
+	        .short("s")
+	        .long("set")
+	        .value_name("KEY=VALUE")
+	        .help("Overrides a configuration field, like board.name=Plop")
+	        .takes_value(true)
+	        .multiple(true)
+	        .number_of_values(1)
+	        .global(true);
+	let board_arg = Arg::with_name("board")
+	        .short("b")
+	        .long("board")
+	        .value_name("ID")
+	        .help("Selects the board, the default one otherwise")
+	        .takes_value(true);
+	let format_arg = Arg::with_name("format")
+	        .short("f")
+	        .long("format")
+	        .value_name("FORMAT")
+	        .help("Sets the format of the posts")
+	        .possible_values(&["csv", "json"])
+	        .default_value("json");
+
+	let matches = App::new("Oxyboard")
+	    	.version("0.2.0")
+	        .author("Olivier Serve <tifauv@gmail.com>")
+	        .about("A board server written in Rust.")
+	        .arg(config_arg)
+	        .arg(set_arg)
+	        .subcommand(SubCommand::with_name("serve")
+	            .about("Starts the server (the default)"))
+	        .subcommand(SubCommand::with_name("import")
+	            .about("Imports the posts of a file into the storage of a board")
+	            .arg(board_arg.clone())
+	            .arg(format_arg.clone())
+	            .arg(Arg::with_name("FILE")
+	                .help("The file of the posts, as exported")
+	                .required(true)))
+	        .subcommand(SubCommand::with_name("export")
+	            .about("Exports the stored posts of a board")
+	            .arg(board_arg.clone())
+	            .arg(format_arg)
+	            .arg(Arg::with_name("output")
+	                .short("o")
+	                .long("output")
+	                .value_name("FILE")
+	                .help("Writes the posts to a file instead of the standard output")
+	                .takes_value(true)))
+	        .subcommand(SubCommand::with_name("prune")
+	            .about("Removes the stored posts of a board older than a day")
+	            .arg(board_arg.clone())
+	            .arg(Arg::with_name("before")
+	                .long("before")
+	                .value_name("YYYY-mm-dd")
+	                .help("The first day of the kept posts")
+	                .takes_value(true)
+	                .required(true)))
+	        .subcommand(SubCommand::with_name("compact")
+	            .about("Removes the deleted posts from the storage of a board")
+	            .arg(board_arg))
+	        .subcommand(SubCommand::with_name("check-config")
+	            .about("Checks the configuration file"))
+	      	.get_matches();
+
+	// Gets a value for config if supplied by user, or defaults to "config/oxyboard.toml"
+	let subcommand = matches.subcommand();
+	let config_file = subcommand.1.and_then(|m| m.value_of("config"))
+			.or_else(|| matches.value_of("config"))
+			.unwrap_or("config/Oxyboard.toml");
+
+	// The overrides may be given before and after the subcommand
+	let mut overrides: Vec<&str> = matches.values_of("set").map(|v| v.collect()).unwrap_or_default();
+	if let Some(args) = subcommand.1 {
+		overrides.extend(args.values_of("set").into_iter().flatten());
+	}
+	let config = load_config(config_file, &overrides);
+
+	// The ids of the boards name their data directories, used by every task
+	if subcommand.0 == "check-config" {
+		check_config(config_file, &config);
+		return;
+	}
+	if subcommand.0 == "serve" || subcommand.0.is_empty() {
+		log_config_source(config_file);
+	}
+	exit_if_invalid(&config);
+
+	match subcommand {
+		("import",       Some(args)) => import(&config, args),
+		("export",       Some(args)) => export(&config, args),
+		("prune",        Some(args)) => prune(&config, args),
+		("compact",      Some(args)) => compact(&config, args),
+		_                            => {
+			// The data directories stay locked until the server and its recorders are done
+			let _locks = lock_boards(&config);
+			if let Err(e) = rocket::execute(rocket(config).launch()) {
+				warn_msg!("The server failed: {}", e);
+
+				// The error may hold the server: dropping it drops the histories,
+				// whose recorders write their queued posts, as process::exit() would not
+				drop(e);
+				process::exit(1);
+			}
+		}
+	}
+}
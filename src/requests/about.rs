@@ -1,4 +1,4 @@
-use crate::core::LockedHistory;
+use crate::core::BoardRegistry;
 use rocket::get;
 use rocket::State;
 use rocket_dyn_templates::Template;
@@ -12,8 +12,8 @@ struct AboutContext<'a> {
 
 
 #[get("/about")]
-pub fn html(p_history: &State<LockedHistory>) -> Template {
-	let history = p_history.read().unwrap();
+pub fn html(p_boards: &State<BoardRegistry>) -> Template {
+	let history = p_boards.default_board().read().unwrap();
 
     Template::render("about", &AboutContext {
         parent: "layout",
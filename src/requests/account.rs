@@ -0,0 +1,145 @@
+//! The handlers for the user accounts and their sessions.
+//!
+//! A session is a private (encrypted and authenticated) cookie holding the
+//! login of the user. The `Session` request guard is the only source of the
+//! login of a post: the login sent by the clients is ignored.
+//!
+//! The passwords are hashed on the blocking threads of the runtime, as it
+//! takes a while.
+
+use crate::core::{AccountRegistry, RateLimiter};
+use crate::core::accounts::{check_login, hash_password, verify_password};
+use crate::requests::post::{Author, TooManyRequests};
+use rocket::{get, post};
+use rocket::State;
+use rocket::form::{Form, FromForm};
+use rocket::http::{Cookie, CookieJar, Status};
+use rocket::request::{self, FromRequest, Outcome, Request};
+use rocket::serde::json::{json, Json, Value};
+use rocket::tokio::task::spawn_blocking;
+use std::io;
+use std::io::ErrorKind;
+
+
+/// The name of the private cookie holding the login of the session.
+pub const SESSION_COOKIE: &str = "session";
+
+
+/// The login of the authenticated user, if any.
+///
+/// It is read from the session cookie, and only kept if the account still
+/// exists. This guard never fails.
+pub struct Session(pub Option<String>);
+
+impl Session {
+	/// Gives the login to record in a post, empty for the anonymous users.
+	pub fn login(&self) -> &str {
+		self.0.as_deref().unwrap_or("")
+	}
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Session {
+	type Error = ();
+
+	async fn from_request(p_request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+		let accounts = p_request.rocket().state::<AccountRegistry>();
+		let login = p_request.cookies()
+				.get_private(SESSION_COOKIE)
+				.map(|c| c.value().to_string())
+				.filter(|l| accounts.is_some_and(|a| a.exists(l)));
+
+		Outcome::Success(Session(login))
+	}
+}
+
+
+/// The credentials sent to register or log in.
+#[derive(FromForm)]
+pub struct Credentials {
+	login    : String,
+	password : String,
+}
+
+
+/// Opens a session for a login.
+fn open_session(p_cookies: &CookieJar<'_>, p_login: &str) {
+	p_cookies.add_private(Cookie::build((SESSION_COOKIE, p_login.to_string()))
+			.http_only(true)
+			.same_site(rocket::http::SameSite::Lax));
+}
+
+
+/// Handles POST requests to create an account.
+///
+/// The user is logged in once the account is created. Answers `201 Created`,
+/// `400 Bad Request` if the login or the password is invalid and
+/// `409 Conflict` if the login is taken. A client trying too often gets a
+/// `429 Too Many Requests`.
+#[post("/account/register", data = "<p_credentials>")]
+pub async fn register(p_credentials: Form<Credentials>, p_author: Author, p_limiter: &State<RateLimiter>, p_accounts: &State<AccountRegistry>, p_cookies: &CookieJar<'_>) -> Result<Result<Status, (Status, String)>, TooManyRequests> {
+	p_author.check_account_rate(p_limiter)?;
+	let Credentials { login, password } = p_credentials.into_inner();
+	let login = login.trim();
+
+	let created = match check_login(login) {
+		Ok(())  => spawn_blocking(move || hash_password(&password)).await
+				.unwrap_or_else(|e| Err(io::Error::other(e)))
+				.and_then(|hash| p_accounts.add(login, hash)),
+		Err(e)  => Err(e)
+	};
+
+	Ok(match created {
+		Ok(()) => {
+			info_msg!("Account '{}' created.", login);
+			open_session(p_cookies, login);
+			Ok(Status::Created)
+		},
+		Err(e) => Err(match e.kind() {
+			ErrorKind::AlreadyExists => (Status::Conflict, e.to_string()),
+			ErrorKind::InvalidInput  => (Status::BadRequest, e.to_string()),
+			_                        => {
+				warn_msg!("Failed to create account '{}': {}", login, e);
+				(Status::InternalServerError, String::from("The account could not be created"))
+			}
+		})
+	})
+}
+
+
+/// Handles POST requests to log in.
+///
+/// Answers `204 No Content` with the session cookie, or `401 Unauthorized`.
+/// A client trying too often gets a `429 Too Many Requests`.
+#[post("/account/login", data = "<p_credentials>")]
+pub async fn login(p_credentials: Form<Credentials>, p_author: Author, p_limiter: &State<RateLimiter>, p_accounts: &State<AccountRegistry>, p_cookies: &CookieJar<'_>) -> Result<Status, TooManyRequests> {
+	p_author.check_account_rate(p_limiter)?;
+	let Credentials { login, password } = p_credentials.into_inner();
+	let login = login.trim();
+
+	let hash = p_accounts.password_hash(login);
+	if spawn_blocking(move || verify_password(hash.as_deref(), &password)).await.unwrap_or(false) {
+		open_session(p_cookies, login);
+		Ok(Status::NoContent)
+	}
+	else {
+		Ok(Status::Unauthorized)
+	}
+}
+
+
+/// Handles POST requests to log out.
+#[post("/account/logout")]
+pub fn logout(p_cookies: &CookieJar<'_>) -> Status {
+	p_cookies.remove_private(SESSION_COOKIE);
+	Status::NoContent
+}
+
+
+/// Handles GET requests for the current session.
+///
+/// Gives the login of the user, `null` if it is not authenticated.
+#[get("/account")]
+pub fn session(p_session: Session) -> Json<Value> {
+	Json(json!({ "login": p_session.0 }))
+}
@@ -0,0 +1,118 @@
+//! The handlers for the archives of the boards.
+//!
+//! The archives are read from the storage backend of a board, so they give
+//! access to the posts that are no longer in its `History`. They are rendered
+//! like the backend, in XML or JSON, the most recent post first.
+
+use crate::core::{BoardRegistry, LockedHistory, Post};
+use crate::requests::backend::{self, Backend};
+use crate::requests::format::{BackendFormat, Formatted};
+use crate::storage::SharedStorage;
+use chrono::NaiveDate;
+use rocket::get;
+use rocket::State;
+use rocket::http::Status;
+use rocket::request::FromParam;
+use std::io;
+
+
+/// The maximum number of ids of a range request.
+pub const MAX_RANGE: u64 = 1000;
+
+
+/// A day of the archives, written `YYYY-mm-dd` in the URLs.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::requests::archive::ArchiveDay;
+/// use rocket::request::FromParam;
+///
+/// assert_eq!(ArchiveDay::from_param("2016-10-26").unwrap().as_str(), "20161026");
+/// assert!(ArchiveDay::from_param("2016-02-30").is_err());
+/// ```
+pub struct ArchiveDay(String);
+
+impl ArchiveDay {
+	/// Gives the day formatted as the beginning of a post time, "YYYYmmdd".
+	pub fn as_str(&self) -> &str {
+		&self.0
+	}
+}
+
+impl<'a> FromParam<'a> for ArchiveDay {
+	type Error = chrono::ParseError;
+
+	fn from_param(p_param: &'a str) -> Result<Self, Self::Error> {
+		NaiveDate::parse_from_str(p_param, "%Y-%m-%d")
+			.map(|d| ArchiveDay(d.format("%Y%m%d").to_string()))
+	}
+}
+
+
+/// Renders posts read from the storage of a board.
+///
+/// The times of the posts are given in the current timezone of the board.
+/// Storage errors are logged and answered with a `500 Internal Server Error`.
+fn archive<F>(p_board_id: &str, p_history: &LockedHistory, p_storage: Option<&SharedStorage>, p_format: BackendFormat, p_read: F) -> Result<Backend, Status>
+		where F: FnOnce(&SharedStorage) -> io::Result<Vec<Post>> {
+	let storage = p_storage.ok_or(Status::NotFound)?;
+	let posts = p_read(storage).map_err(|e| {
+		warn_msg!("Failed to read the archives of board '{}' from '{}': {}", p_board_id, storage.location(), e);
+		Status::InternalServerError
+	})?;
+
+	let history = p_history.read().unwrap();
+	let posts: Vec<Post> = posts.iter().map(|p| p.in_timezone(history.timezone())).collect();
+	Ok(backend::render(p_board_id, &history, posts.iter().rev(), p_format))
+}
+
+
+/// Checks an id range, which must not be reversed nor larger than `MAX_RANGE`.
+fn check_range(p_first_id: u64, p_last_id: u64) -> Result<(), Status> {
+	if p_last_id < p_first_id || p_last_id - p_first_id >= MAX_RANGE {
+		return Err(Status::BadRequest);
+	}
+	Ok(())
+}
+
+
+/// Handles GET requests for the posts of a day.
+///
+/// Uses a :day URL parameter formatted as `YYYY-mm-dd`, which may end with a format extension.
+#[get("/archive/day/<p_day>")]
+pub fn day(p_day: Formatted<ArchiveDay>, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Result<Backend, Status> {
+	archive(p_boards.default_id(), p_boards.default_board(), p_boards.default_storage(), p_day.format_or(p_format),
+			|s| s.posts_of_day(p_day.value.as_str()))
+}
+
+
+/// Handles GET requests for the posts of a day of a given board.
+#[get("/b/<p_board>/archive/day/<p_day>")]
+pub fn board_day(p_board: &str, p_day: Formatted<ArchiveDay>, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Result<Backend, Status> {
+	let history = p_boards.get(p_board).ok_or(Status::NotFound)?;
+	archive(p_board, history, p_boards.storage(p_board), p_day.format_or(p_format),
+			|s| s.posts_of_day(p_day.value.as_str()))
+}
+
+
+/// Handles GET requests for the posts of an id range.
+///
+/// Both ids are included. The last one may end with a format extension.
+/// The range cannot span more than `MAX_RANGE` ids.
+#[get("/archive/posts/<p_first_id>/<p_last_id>")]
+pub fn range(p_first_id: u64, p_last_id: Formatted<u64>, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Result<Backend, Status> {
+	check_range(p_first_id, p_last_id.value)?;
+	archive(p_boards.default_id(), p_boards.default_board(), p_boards.default_storage(), p_last_id.format_or(p_format),
+			|s| s.posts_between(p_first_id, p_last_id.value))
+}
+
+
+/// Handles GET requests for the posts of an id range of a given board.
+#[get("/b/<p_board>/archive/posts/<p_first_id>/<p_last_id>")]
+pub fn board_range(p_board: &str, p_first_id: u64, p_last_id: Formatted<u64>, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Result<Backend, Status> {
+	let history = p_boards.get(p_board).ok_or(Status::NotFound)?;
+	check_range(p_first_id, p_last_id.value)?;
+	archive(p_board, history, p_boards.storage(p_board), p_last_id.format_or(p_format),
+			|s| s.posts_between(p_first_id, p_last_id.value))
+}
@@ -1,32 +1,33 @@
 //!
 /// The handlers for backend requests.
-
-use crate::core::{LockedHistory, Post};
-use rocket::get;
+///
+/// The backends are rendered in XML by default. A JSON rendering is given
+/// when the client prefers `application/json` in its `Accept` header, or
+/// when the URL ends with a `.json` extension (`/backend.json`,
+/// `/backend/last/<n>.json`, `/backend/since/<id>.json`).
+///
+/// The backends support conditional GET requests (see the `cache` module).
+///
+/// The `/backend/since/<id>` backends can be long-polled: with a
+/// `wait=<seconds>` query parameter, the request is held until a post newer
+/// than `<id>` is added to the board, or the delay expires.
+
+use crate::core::{BoardRegistry, History, LockedHistory, Post, PostBroadcaster};
+use crate::core::broadcast::BoardEvent;
+use crate::requests::cache::{Cached, Conditions, Validators};
+use crate::requests::format::{BackendFormat, Formatted};
+use crate::requests::view::PostViewModel;
+use rocket::{get, FromForm, Responder, Shutdown};
 use rocket::State;
+use rocket::serde::json::{json, Json, Value};
 use rocket_dyn_templates::Template;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time;
 
 
-#[derive(serde::Serialize)]
-struct PostViewModel<'a> {
-	id         : u64,
-	time       : &'a str,
-	user_agent : &'a str,
-	message    : &'a str,
-	login      : &'a str,
-}
-
-impl<'a> PostViewModel<'a> {
-	fn new(p_post: &Post) -> PostViewModel {
-		PostViewModel {
-			id         : p_post.id(),
-			time       : p_post.time(),
-			user_agent : p_post.user_agent(),
-			message    : p_post.message(),
-			login      : p_post.login(),
-		}
-	}
-}
+/// The maximum delay a long-polling request waits for a new post, in seconds.
+pub const MAX_WAIT: u64 = 60;
 
 
 #[derive(serde::Serialize)]
@@ -37,78 +38,187 @@ struct BackendContext<'a> {
 }
 
 
-/// Handles GET requests for the full backend.
-///
-/// Builds the XML backend and returns it.
-#[get("/backend")]
-pub fn full_xml(p_history: &State<LockedHistory>) -> Template {
+/// The parameters of the `/backend/since/<id>` requests.
+#[derive(FromForm)]
+pub struct SinceParams {
+	/// The delay to wait for a new post, in seconds
+	wait : Option<u64>,
+}
+
+
+/// A backend rendered in one of the `BackendFormat`s.
+#[derive(Responder)]
+pub enum Backend {
+	Xml(Box<Template>),
+	Json(Json<Value>),
+}
+
+
+/// Renders the backend of a board with the given posts.
+pub(crate) fn render<'a>(p_board_id: &str, p_history: &'a History, p_posts: impl Iterator<Item = &'a Post>, p_format: BackendFormat) -> Backend {
+	let posts: Vec<PostViewModel> = p_posts.map(PostViewModel::new).collect();
+
+	match p_format {
+		BackendFormat::Xml  => Backend::Xml(Box::new(Template::render("backend", &BackendContext {
+			parent: "layout",
+			board_name: p_history.board_name(),
+			posts
+		}))),
+		BackendFormat::Json => Backend::Json(Json(json!({
+			"board": {
+				"id"           : p_board_id,
+				"name"         : p_history.board_name(),
+				"history_size" : p_history.max_size(),
+			},
+			"posts": posts
+		})))
+	}
+}
+
+
+/// Renders a backend of a board, unless the copy of the client is still valid.
+fn cached_backend<'a, F, I>(p_board_id: &str, p_history: &'a History, p_conditions: &Conditions, p_format: BackendFormat, p_posts: F) -> Cached<Backend>
+		where F: FnOnce() -> I,
+		      I: Iterator<Item = &'a Post> {
+	let validators = Validators::of(p_history, p_format);
+	if validators.is_fresh(p_conditions) {
+		return Cached::NotModified(validators);
+	}
+
+	Cached::Fresh(render(p_board_id, p_history, p_posts(), p_format), validators)
+}
+
+
+/// Builds the full backend of a board.
+fn full_backend(p_board_id: &str, p_history: &LockedHistory, p_conditions: &Conditions, p_format: BackendFormat) -> Cached<Backend> {
 	let history = p_history.read().unwrap();
 
-	let mut posts_view = Vec::new();
-	for post in history.iter()
+	cached_backend(p_board_id, &history, p_conditions, p_format, || history.iter().rev())
+}
+
+
+/// Builds the backend of a board containing its last n messages.
+fn last_backend(p_board_id: &str, p_history: &LockedHistory, p_size: usize, p_conditions: &Conditions, p_format: BackendFormat) -> Cached<Backend> {
+	let history = p_history.read().unwrap();
+
+	cached_backend(p_board_id, &history, p_conditions, p_format, || history.iter()
 		.rev()
-		.map(|ref p| PostViewModel::new(&p))
-		.collect::<Vec<_>>() {
-		posts_view.push(post);
+		.take(p_size))
+}
+
+
+/// Builds the backend of a board containing the messages after a given post id.
+fn since_backend(p_board_id: &str, p_history: &LockedHistory, p_post_id: u64, p_conditions: &Conditions, p_format: BackendFormat) -> Cached<Backend> {
+	let history = p_history.read().unwrap();
+
+	cached_backend(p_board_id, &history, p_conditions, p_format, || history.iter()
+		.filter(move |p| p.id() > p_post_id)
+		.rev())
+}
+
+
+/// Waits until a post newer than `p_post_id` is added to a board.
+///
+/// Returns at once if the history already has such a post, and gives up
+/// after `p_wait` seconds (at most `MAX_WAIT`) or when the server shuts down.
+async fn wait_for_posts(p_history: &LockedHistory, p_broadcaster: &PostBroadcaster, p_post_id: u64, p_wait: u64, p_end: Shutdown) {
+	if p_wait == 0 {
+		return;
 	}
 
-	Template::render("backend", &BackendContext {
-		parent: "layout",
-		board_name: &history.board_name(),
-		posts: posts_view
-	})
+	// Subscribe before reading the history so that no post can be missed in between
+	let mut events = p_broadcaster.subscribe();
+	if p_history.read().unwrap().last_post_id() > p_post_id {
+		return;
+	}
+
+	let new_post = async {
+		loop {
+			match events.recv().await {
+				Ok(BoardEvent::PostAdded(post)) if post.id() > p_post_id => break,
+				Ok(_)                                                   => continue,
+				// Some posts were missed, or the board is gone
+				Err(RecvError::Lagged(_)) | Err(RecvError::Closed)      => break
+			}
+		}
+	};
+
+	rocket::tokio::select! {
+		_ = time::timeout(Duration::from_secs(p_wait.min(MAX_WAIT)), new_post) => {},
+		_ = p_end => {},
+	}
+}
+
+
+/// Handles GET requests for the full backend.
+///
+/// Builds the backend in the format requested by the `Accept` header and returns it.
+#[get("/backend")]
+pub fn full(p_conditions: Conditions, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Cached<Backend> {
+	full_backend(p_boards.default_id(), p_boards.default_board(), &p_conditions, p_format)
+}
+
+
+/// Handles GET requests for the full JSON backend.
+#[get("/backend.json")]
+pub fn full_json(p_conditions: Conditions, p_boards: &State<BoardRegistry>) -> Cached<Backend> {
+	full_backend(p_boards.default_id(), p_boards.default_board(), &p_conditions, BackendFormat::Json)
+}
+
+
+/// Handles GET requests for the full backend of a given board.
+#[get("/b/<p_board>/backend")]
+pub fn board_full(p_board: &str, p_conditions: Conditions, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Option<Cached<Backend>> {
+	p_boards.get(p_board).map(|h| full_backend(p_board, h, &p_conditions, p_format))
+}
+
+
+/// Handles GET requests for the full JSON backend of a given board.
+#[get("/b/<p_board>/backend.json")]
+pub fn board_full_json(p_board: &str, p_conditions: Conditions, p_boards: &State<BoardRegistry>) -> Option<Cached<Backend>> {
+	p_boards.get(p_board).map(|h| full_backend(p_board, h, &p_conditions, BackendFormat::Json))
 }
 
 
 /// Handles GET requests for a backend containing the last n messages.
 ///
-/// Uses a :size URL parameter.
+/// Uses a :size URL parameter, which may end with a format extension.
 #[get("/backend/last/<p_size>")]
-pub fn last_xml(p_size: usize, p_history: &State<LockedHistory>) -> Template {
-	let history = p_history.read().unwrap();
+pub fn last(p_size: Formatted<usize>, p_conditions: Conditions, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Cached<Backend> {
+	last_backend(p_boards.default_id(), p_boards.default_board(), p_size.value, &p_conditions, p_size.format_or(p_format))
+}
 
-	let mut posts_view = Vec::new();
-	for post in history.iter()
-		.rev()
-		.take(p_size)
-		.map(|ref p| PostViewModel::new(&p))
-		.collect::<Vec<_>>() {
-		posts_view.push(post);
-	}
 
-	Template::render("backend", &BackendContext {
-		parent: "layout",
-		board_name: &history.board_name(),
-		posts: posts_view
-	})
+/// Handles GET requests for a backend of a given board containing its last n messages.
+#[get("/b/<p_board>/backend/last/<p_size>")]
+pub fn board_last(p_board: &str, p_size: Formatted<usize>, p_conditions: Conditions, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Option<Cached<Backend>> {
+	p_boards.get(p_board).map(|h| last_backend(p_board, h, p_size.value, &p_conditions, p_size.format_or(p_format)))
 }
 
 
 /// Handles GET requests for a backend since a given post id.
 ///
-/// Uses a :lastId URL parameter.
+/// Uses a :lastId URL parameter, which may end with a format extension.
 ///
-/// Builds the XML backend containing only the posts having an id
+/// Builds the backend containing only the posts having an id
 /// greater than the given one. If no :lastId parameter is found,
-/// uses "1" as the lastId.
+/// uses "1" as the lastId. With a `wait` query parameter, waits for such
+/// a post to be added first.
 ///
 /// @returns the backend
-#[get("/backend/since/<p_post_id>")]
-pub fn since_xml(p_post_id: u64, p_history: &State<LockedHistory>) -> Template {
-	let history = p_history.read().unwrap();
+#[get("/backend/since/<p_post_id>?<p_params..>")]
+pub async fn since(p_post_id: Formatted<u64>, p_params: SinceParams, p_conditions: Conditions, p_format: BackendFormat, p_boards: &State<BoardRegistry>, p_end: Shutdown) -> Cached<Backend> {
+	wait_for_posts(p_boards.default_board(), p_boards.default_broadcaster(), p_post_id.value, p_params.wait.unwrap_or(0), p_end).await;
+	since_backend(p_boards.default_id(), p_boards.default_board(), p_post_id.value, &p_conditions, p_post_id.format_or(p_format))
+}
 
-	let mut posts_view = Vec::new();
-	for post in history.iter()
-		.filter(|p| p.id() > p_post_id)
-		.rev()
-		.map(|ref p| PostViewModel::new(&p))
-		.collect::<Vec<_>>() {
-		posts_view.push(post);
-	}
 
-	Template::render("backend", &BackendContext {
-		parent: "layout",
-		board_name: &history.board_name(),
-		posts: posts_view
-	})
+/// Handles GET requests for a backend of a given board since a given post id.
+#[get("/b/<p_board>/backend/since/<p_post_id>?<p_params..>")]
+pub async fn board_since(p_board: &str, p_post_id: Formatted<u64>, p_params: SinceParams, p_conditions: Conditions, p_format: BackendFormat, p_boards: &State<BoardRegistry>, p_end: Shutdown) -> Option<Cached<Backend>> {
+	let history = p_boards.get(p_board)?;
+	let broadcaster = p_boards.broadcaster(p_board)?;
+
+	wait_for_posts(history, broadcaster, p_post_id.value, p_params.wait.unwrap_or(0), p_end).await;
+	Some(since_backend(p_board, history, p_post_id.value, &p_conditions, p_post_id.format_or(p_format)))
 }
@@ -1,4 +1,5 @@
-use crate::core::LockedHistory;
+use crate::core::{BoardRegistry, LockedHistory};
+use crate::requests::clients_config::ClientsConfig;
 use rocket::get;
 use rocket::State;
 use rocket_dyn_templates::Template;
@@ -7,16 +8,37 @@ use rocket_dyn_templates::Template;
 #[derive(serde::Serialize)]
 struct BoardContext<'a> {
     parent: &'static str,
-    board_name: &'a String
+    board_name: &'a String,
+	base_url: String,
+	refresh_interval: u64
 }
 
 
-#[get("/board")]
-pub fn html(p_history: &State<LockedHistory>) -> Template {
+/// Renders the board page, the board's routes being found under `p_base_url`.
+///
+/// The page polls the backend every `p_refresh_interval` seconds when the
+/// browser cannot follow the events stream.
+fn render(p_history: &LockedHistory, p_base_url: String, p_refresh_interval: u64) -> Template {
 	let history = p_history.read().unwrap();
 
 	Template::render("board", &BoardContext {
         parent: "layout",
-        board_name: &history.board_name()
+        board_name: history.board_name(),
+		base_url: p_base_url,
+		refresh_interval: p_refresh_interval
     })
 }
+
+
+#[get("/board")]
+pub fn html(p_boards: &State<BoardRegistry>, p_clients: &State<ClientsConfig>) -> Template {
+	let refresh_interval = p_clients.get(p_boards.default_id()).map_or(8, |b| b.refresh_interval);
+	render(p_boards.default_board(), String::new(), refresh_interval)
+}
+
+
+#[get("/b/<p_board>/board")]
+pub fn board_html(p_board: &str, p_boards: &State<BoardRegistry>, p_clients: &State<ClientsConfig>) -> Option<Template> {
+	let refresh_interval = p_clients.get(p_board).map_or(8, |b| b.refresh_interval);
+	p_boards.get(p_board).map(|h| render(h, format!("/b/{}", p_board), refresh_interval))
+}
@@ -0,0 +1,208 @@
+//! The HTTP caching of the backends.
+//!
+//! The backends are validated by an `ETag`, built from the last post id and
+//! the revision of the history, and by a `Last-Modified` date, the last time
+//! the history was changed. A client sending them back in `If-None-Match` or
+//! `If-Modified-Since` headers gets a `304 Not Modified` while nothing was
+//! posted or moderated, without the backend being rendered again.
+//!
+//! The revisions and the moderation times are only kept in memory: the
+//! validators of a previous run of the server are never taken as valid, the
+//! entity tags holding the start time of the server, which is also the
+//! earliest `Last-Modified` date.
+
+use crate::core::History;
+use crate::requests::format::BackendFormat;
+use chrono::{DateTime, FixedOffset, Utc};
+use rocket::http::{Header, Status};
+use rocket::request::{self, FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use std::sync::OnceLock;
+
+
+/// Gives the time the server started, in fact the first time it is asked.
+fn started() -> DateTime<Utc> {
+	static STARTED: OnceLock<DateTime<Utc>> = OnceLock::new();
+	*STARTED.get_or_init(Utc::now)
+}
+
+
+/// The validators of a rendered history.
+pub struct Validators {
+	/// The entity tag, quotes included
+	etag          : String,
+	/// The last modification time, if it is in a past second
+	last_modified : Option<DateTime<Utc>>,
+}
+
+impl Validators {
+	/// Builds the validators of a history rendered in a format, when it is
+	/// read.
+	///
+	/// The history is taken as modified when the server started, as the
+	/// moderations of a previous run are not known.
+	pub fn of(p_history: &History, p_format: BackendFormat) -> Validators {
+		Validators::new(
+				p_history.last_post_id(),
+				p_history.revision(),
+				p_format,
+				p_history.last_modified().max(Some(started())),
+				Utc::now())
+	}
+
+
+	/// Builds the validators from their components, `p_now` being the time
+	/// the history was read.
+	///
+	/// The format is part of the entity tag, as the XML and JSON backends are
+	/// served at the same URLs, and so is the start time of the server, as
+	/// the revisions restart at 0 with it.
+	///
+	/// The `Last-Modified` date has a precision of a second: it is not given
+	/// while the history may still change within the second it was modified.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use chrono::{Duration, Utc};
+	/// use oxyboard::requests::cache::{Conditions, Validators};
+	/// use oxyboard::requests::format::BackendFormat;
+	///
+	/// let validators = Validators::new(42, 1, BackendFormat::Json, None, Utc::now());
+	/// assert!(validators.etag().ends_with("-42-1-json\""));
+	///
+	/// // A post may be added later in the second of the last modification
+	/// let modified = Utc::now();
+	/// let last_second = modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+	/// let validators = Validators::new(42, 0, BackendFormat::Xml, Some(modified), modified);
+	/// assert!(!validators.is_fresh(&Conditions::new(None, Some(&last_second))));
+	///
+	/// let validators = Validators::new(42, 0, BackendFormat::Xml, Some(modified), modified + Duration::seconds(1));
+	/// assert!(validators.is_fresh(&Conditions::new(None, Some(&last_second))));
+	/// ```
+	pub fn new(p_last_post_id: u64, p_revision: u64, p_format: BackendFormat, p_last_modified: Option<DateTime<Utc>>, p_now: DateTime<Utc>) -> Validators {
+		let format = match p_format {
+			BackendFormat::Xml  => "xml",
+			BackendFormat::Json => "json"
+		};
+
+		Validators {
+			etag          : format!("\"{:x}-{}-{}-{}\"", started().timestamp_millis(), p_last_post_id, p_revision, format),
+			last_modified : p_last_modified.filter(|m| m.timestamp() < p_now.timestamp()),
+		}
+	}
+
+
+	/// Gives the entity tag.
+	pub fn etag(&self) -> &str {
+		&self.etag
+	}
+
+
+	/// Tells whether the copy of the client is still valid.
+	///
+	/// The `If-None-Match` condition takes precedence over the
+	/// `If-Modified-Since` one, which is ignored when it is given.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use chrono::{DateTime, Utc};
+	/// use oxyboard::requests::cache::{Conditions, Validators};
+	/// use oxyboard::requests::format::BackendFormat;
+	///
+	/// let modified = DateTime::parse_from_rfc2822("Wed, 26 Oct 2016 12:00:00 GMT").unwrap().with_timezone(&Utc);
+	/// let validators = Validators::new(42, 0, BackendFormat::Xml, Some(modified), Utc::now());
+	/// let previous = Validators::new(41, 0, BackendFormat::Xml, Some(modified), Utc::now());
+	///
+	/// assert!(validators.is_fresh(&Conditions::new(Some(validators.etag()), None)));
+	/// assert!(validators.is_fresh(&Conditions::new(Some(&format!("{}, W/{}", previous.etag(), validators.etag())), None)));
+	/// assert!(!validators.is_fresh(&Conditions::new(Some(previous.etag()), None)));
+	/// assert!(!validators.is_fresh(&Conditions::new(Some("\"42-0-xml\""), None)));
+	///
+	/// assert!(validators.is_fresh(&Conditions::new(None, Some("Wed, 26 Oct 2016 12:00:00 GMT"))));
+	/// assert!(!validators.is_fresh(&Conditions::new(None, Some("Wed, 26 Oct 2016 11:59:59 GMT"))));
+	///
+	/// assert!(!validators.is_fresh(&Conditions::new(None, None)));
+	/// ```
+	pub fn is_fresh(&self, p_conditions: &Conditions) -> bool {
+		if let Some(tags) = &p_conditions.if_none_match {
+			return tags.split(',')
+					.map(|t| t.trim())
+					.map(|t| t.strip_prefix("W/").unwrap_or(t))
+					.any(|t| t == "*" || t == self.etag);
+		}
+
+		match (&p_conditions.if_modified_since, &self.last_modified) {
+			(Some(since), Some(modified)) => modified.timestamp() <= since.timestamp(),
+			_                             => false
+		}
+	}
+
+
+	/// Adds the validators to a response.
+	fn set_headers(&self, p_response: &mut Response<'_>) {
+		p_response.set_header(Header::new("ETag", self.etag.clone()));
+		if let Some(modified) = &self.last_modified {
+			p_response.set_header(Header::new("Last-Modified", modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string()));
+		}
+		// The format of the backends depends on the Accept header
+		p_response.set_header(Header::new("Vary", "Accept"));
+	}
+}
+
+
+/// The conditions of a conditional GET request.
+///
+/// This guard never fails; the invalid dates are ignored.
+pub struct Conditions {
+	if_none_match     : Option<String>,
+	if_modified_since : Option<DateTime<FixedOffset>>,
+}
+
+impl Conditions {
+	/// Builds the conditions from the values of the `If-None-Match` and
+	/// `If-Modified-Since` headers.
+	pub fn new(p_if_none_match: Option<&str>, p_if_modified_since: Option<&str>) -> Conditions {
+		Conditions {
+			if_none_match     : p_if_none_match.map(String::from),
+			if_modified_since : p_if_modified_since.and_then(|d| DateTime::parse_from_rfc2822(d).ok()),
+		}
+	}
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Conditions {
+	type Error = ();
+
+	async fn from_request(p_request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+		let headers = p_request.headers();
+		Outcome::Success(Conditions::new(headers.get_one("If-None-Match"), headers.get_one("If-Modified-Since")))
+	}
+}
+
+
+/// A response that may be cached by the client.
+pub enum Cached<R> {
+	/// The copy of the client is still valid
+	NotModified(Validators),
+	/// A new response
+	Fresh(R, Validators),
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Cached<R> {
+	fn respond_to(self, p_request: &'r Request<'_>) -> response::Result<'o> {
+		match self {
+			Cached::NotModified(validators) => {
+				let mut response = Response::build().status(Status::NotModified).finalize();
+				validators.set_headers(&mut response);
+				Ok(response)
+			},
+			Cached::Fresh(responder, validators) => {
+				let mut response = responder.respond_to(p_request)?;
+				validators.set_headers(&mut response);
+				Ok(response)
+			}
+		}
+	}
+}
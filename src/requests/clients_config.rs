@@ -1,22 +1,196 @@
-use crate::core::LockedHistory;
+//! The handlers for the configuration of the clients.
+//!
+//! The `/clients/config` page tells the users of the tribune clients how to
+//! reach the boards: the URLs of their backends and post forms, the name of
+//! the message field, the maximum length of the messages and the advised
+//! refresh interval. The same descriptors are given in the formats the
+//! clients import: a coincoin-style XML one (`/clients/config.xml`) and an
+//! olcc-style JSON one (`/clients/config.json`).
+
+use crate::config::{BoardParams, Config};
 use rocket::get;
 use rocket::State;
+use rocket::http::ContentType;
+use rocket::request::{self, FromRequest, Outcome, Request};
+use rocket::serde::json::{json, Json, Value};
 use rocket_dyn_templates::Template;
 
 
+/// The name of the message field of the post form.
+pub const MESSAGE_FIELD: &str = "message";
+
+
+/// What the clients need to know about a board.
+#[derive(Clone, serde::Serialize)]
+pub struct BoardDescriptor {
+	pub id                 : String,
+	pub name               : String,
+	/// The prefix of the routes of the board, empty for the default one
+	pub path               : String,
+	pub max_message_length : usize,
+	pub refresh_interval   : u64,
+}
+
+impl BoardDescriptor {
+	fn new(p_board: &BoardParams, p_path: String) -> BoardDescriptor {
+		BoardDescriptor {
+			id                 : p_board.id.clone(),
+			name               : p_board.name.clone(),
+			path               : p_path,
+			max_message_length : p_board.max_message_length,
+			refresh_interval   : p_board.refresh_interval,
+		}
+	}
+}
+
+
+/// The descriptors of the served boards, the default one first.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::config;
+/// use oxyboard::requests::clients_config::ClientsConfig;
+///
+/// let clients = ClientsConfig::new(&config::default());
+/// assert_eq!(clients.boards().len(), 1);
+/// assert_eq!(clients.get("default").unwrap().path, "");
+/// assert_eq!(clients.get("default").unwrap().max_message_length, 512);
+/// ```
+pub struct ClientsConfig {
+	boards : Vec<BoardDescriptor>,
+}
+
+impl ClientsConfig {
+	/// Builds the descriptors of the boards of a configuration.
+	pub fn new(p_config: &Config) -> ClientsConfig {
+		let mut boards = vec![ BoardDescriptor::new(&p_config.board, String::new()) ];
+		boards.extend(p_config.boards.iter().map(|b| BoardDescriptor::new(b, format!("/b/{}", b.id))));
+
+		ClientsConfig {
+			boards
+		}
+	}
+
+
+	/// Gives the descriptors of all the boards.
+	pub fn boards(&self) -> &[BoardDescriptor] {
+		&self.boards
+	}
+
+
+	/// Gives the descriptor of the board with the given id, if any.
+	pub fn get(&self, p_id: &str) -> Option<&BoardDescriptor> {
+		self.boards.iter().find(|b| b.id == p_id)
+	}
+}
+
+
+/// The URL the server is reached at, like `http://localhost:8000`.
+///
+/// It is built from the `Host` header of the request, or from the address
+/// and the port Rocket listens to.
+pub struct BaseUrl(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BaseUrl {
+	type Error = ();
+
+	async fn from_request(p_request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+		let config = p_request.rocket().config();
+		let scheme = if config.tls_enabled() { "https" } else { "http" };
+		let host = match p_request.host() {
+			Some(host) => host.to_string(),
+			None       => format!("{}:{}", config.address, config.port)
+		};
+
+		Outcome::Success(BaseUrl(format!("{}://{}", scheme, host)))
+	}
+}
+
+
 #[derive(serde::Serialize)]
 struct ClientsConfigContext<'a> {
     parent: &'static str,
-    board_name: &'a String
+    board_name: &'a str,
+	boards: Vec<BoardUrls<'a>>
+}
+
+
+/// The descriptor of a board, with its absolute URLs.
+#[derive(serde::Serialize)]
+struct BoardUrls<'a> {
+	#[serde(flatten)]
+	board         : &'a BoardDescriptor,
+	board_url     : String,
+	backend_url   : String,
+	post_url      : String,
+	post_field    : &'static str,
+	events_url    : String,
+	websocket_url : String,
+	login_url     : String,
+}
+
+impl<'a> BoardUrls<'a> {
+	fn new(p_board: &'a BoardDescriptor, p_base_url: &BaseUrl) -> BoardUrls<'a> {
+		let root = format!("{}{}", p_base_url.0, p_board.path);
+		let websocket_root = root.replacen("http", "ws", 1);
+
+		BoardUrls {
+			board         : p_board,
+			board_url     : format!("{}/board", root),
+			backend_url   : format!("{}/backend", root),
+			post_url      : format!("{}/post", root),
+			post_field    : MESSAGE_FIELD,
+			events_url    : format!("{}/events", root),
+			websocket_url : format!("{}/ws", websocket_root),
+			login_url     : format!("{}/account/login", p_base_url.0),
+		}
+	}
 }
 
 
+/// Builds the context of the descriptors.
+fn context<'a>(p_clients: &'a ClientsConfig, p_base_url: &BaseUrl) -> ClientsConfigContext<'a> {
+	ClientsConfigContext {
+		parent: "layout",
+		board_name: &p_clients.boards[0].name,
+		boards: p_clients.boards.iter().map(|b| BoardUrls::new(b, p_base_url)).collect()
+	}
+}
+
+
+/// Handles GET requests for the configuration page of the clients.
 #[get("/clients/config")]
-pub fn html(p_history: &State<LockedHistory>) -> Template {
-	let history = p_history.read().unwrap();
+pub fn html(p_clients: &State<ClientsConfig>, p_base_url: BaseUrl) -> Template {
+	Template::render("clients_config", context(p_clients, &p_base_url))
+}
+
+
+/// Handles GET requests for the coincoin-style XML descriptor of the boards.
+#[get("/clients/config.xml")]
+pub fn xml(p_clients: &State<ClientsConfig>, p_base_url: BaseUrl) -> (ContentType, Template) {
+	(ContentType::XML, Template::render("coincoin", context(p_clients, &p_base_url)))
+}
+
+
+/// Handles GET requests for the olcc-style JSON descriptor of the boards.
+#[get("/clients/config.json")]
+pub fn json(p_clients: &State<ClientsConfig>, p_base_url: BaseUrl) -> Json<Value> {
+	let boards: Vec<Value> = p_clients.boards.iter()
+			.map(|b| BoardUrls::new(b, &p_base_url))
+			.map(|b| json!({
+				"name"         : b.board.id,
+				"title"        : b.board.name,
+				"backendURL"   : b.backend_url,
+				"postURL"      : b.post_url,
+				"postData"     : format!("{}=%m", MESSAGE_FIELD),
+				"maxLength"    : b.board.max_message_length,
+				"refresh"      : b.board.refresh_interval,
+				"eventsURL"    : b.events_url,
+				"websocketURL" : b.websocket_url,
+			}))
+			.collect();
 
-	Template::render("clients_config", &ClientsConfigContext {
-        parent: "layout",
-        board_name: &history.board_name()
-    })
+	Json(json!({ "boards": boards }))
 }
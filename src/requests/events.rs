@@ -0,0 +1,132 @@
+//! The server-sent events stream of the new posts.
+//!
+//! Each new post is sent as a `post` event whose data is the JSON
+//! representation of the post and whose id is the post id. A client
+//! reconnecting with a `Last-Event-ID` header (or a `since` query parameter)
+//! first receives the posts it has missed, as long as they are still in the
+//! `History`.
+//!
+//! The moderated posts are sent as `delete` or `mask` events, without id,
+//! whose data is the JSON representation of the deleted post or of its
+//! masked copy.
+
+use crate::core::{BoardRegistry, LockedHistory, PostBroadcaster};
+use crate::core::broadcast::BoardEvent;
+use crate::core::history::Moderation;
+use crate::requests::view::PostViewModel;
+use rocket::{get, Shutdown, State};
+use rocket::request::{self, FromRequest, Outcome, Request};
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+
+
+/// The id of the last event received by a reconnecting client.
+pub struct LastEventId(Option<u64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+	type Error = ();
+
+	async fn from_request(p_request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+		Outcome::Success(LastEventId(p_request.headers()
+				.get_one("Last-Event-ID")
+				.and_then(|id| id.trim().parse().ok())))
+	}
+}
+
+
+/// Collects the events of the posts of the history newer than `p_last_id`.
+///
+/// `p_last_id` is updated to the id of the last collected post.
+fn missed_events(p_history: &LockedHistory, p_last_id: &mut u64) -> Vec<Event> {
+	let history = p_history.read().unwrap();
+
+	let since = *p_last_id;
+	let mut events = Vec::new();
+	for post in history.iter().filter(|p| p.id() > since) {
+		events.push(post_event(&PostViewModel::new(post)));
+		*p_last_id = post.id();
+	}
+	events
+}
+
+
+/// Builds the event of a post.
+fn post_event(p_post: &PostViewModel) -> Event {
+	Event::json(p_post)
+		.event("post")
+		.id(p_post.id.to_string())
+}
+
+
+/// Builds the event of a moderated post.
+///
+/// It has no id, so that it does not change the `Last-Event-ID` of the client.
+fn moderation_event(p_post: &PostViewModel, p_moderation: Moderation) -> Event {
+	Event::json(p_post)
+		.event(match p_moderation {
+			Moderation::Delete => "delete",
+			Moderation::Mask   => "mask",
+		})
+}
+
+
+/// Streams the new posts of a board.
+///
+/// If `p_last_id` is given, the posts of the history newer than it are sent
+/// first. Otherwise, only the posts added after the connection are sent.
+fn stream<'r>(p_history: &'r LockedHistory, p_broadcaster: &PostBroadcaster, p_last_id: Option<u64>, mut p_end: Shutdown) -> EventStream![Event + 'r] {
+	// Subscribe before reading the history so that no post can be missed in between
+	let mut receiver = p_broadcaster.subscribe();
+	let mut catch_up = p_last_id.is_some();
+	let mut last_id = match p_last_id {
+		Some(id) => id,
+		None     => p_history.read().unwrap().iter().last().map(|p| p.id()).unwrap_or(0)
+	};
+
+	EventStream! {
+		loop {
+			if catch_up {
+				for event in missed_events(p_history, &mut last_id) {
+					yield event;
+				}
+				catch_up = false;
+			}
+
+			let received = select! {
+				received = receiver.recv() => received,
+				_ = &mut p_end => break,
+			};
+
+			match received {
+				Ok(BoardEvent::PostAdded(post)) => if post.id() > last_id {
+					last_id = post.id();
+					yield post_event(&PostViewModel::new(&post));
+				},
+				Ok(BoardEvent::PostDeleted(post, moderation)) => yield moderation_event(&PostViewModel::new(&post), moderation),
+				// Some events were dropped, read them from the history
+				Err(RecvError::Lagged(_)) => catch_up = true,
+				Err(RecvError::Closed)    => break,
+			}
+		}
+	}
+}
+
+
+/// Handles GET requests for the events stream of the default board.
+///
+/// The `Last-Event-ID` header takes precedence over the `since` parameter.
+#[get("/events?<since>")]
+pub fn posts(since: Option<u64>, p_last_id: LastEventId, p_boards: &State<BoardRegistry>, p_end: Shutdown) -> EventStream![Event + '_] {
+	stream(p_boards.default_board(), p_boards.default_broadcaster(), p_last_id.0.or(since), p_end)
+}
+
+
+/// Handles GET requests for the events stream of a given board.
+#[get("/b/<p_board>/events?<since>")]
+pub fn board_posts<'r>(p_board: &str, since: Option<u64>, p_last_id: LastEventId, p_boards: &'r State<BoardRegistry>, p_end: Shutdown) -> Option<EventStream![Event + 'r]> {
+	let history = p_boards.get(p_board)?;
+	let broadcaster = p_boards.broadcaster(p_board)?;
+	Some(stream(history, broadcaster, p_last_id.0.or(since), p_end))
+}
@@ -0,0 +1,145 @@
+//! The handlers for the Atom and RSS feeds.
+//!
+//! The feeds hold the posts of the history, the most recent first. Each entry
+//! has a stable id built from the board and the post id, and links to the
+//! post in the board page. The `author` query parameter keeps only the posts
+//! of an author (its login, or its user agent for anonymous posts).
+
+use crate::core::{BoardRegistry, LockedHistory, Post};
+use chrono::{DateTime, Utc};
+use rocket::{get, FromForm};
+use rocket::State;
+use rocket::http::ContentType;
+use rocket_dyn_templates::Template;
+
+
+/// The maximum number of characters of the message in an entry title.
+const TITLE_LENGTH: usize = 80;
+
+
+/// A feed, rendered with its media type.
+pub type Feed = (ContentType, Template);
+
+
+/// The parameters of a feed request.
+#[derive(FromForm)]
+pub struct FeedParams<'r> {
+	/// The login, or the user agent for anonymous posts, of the author
+	author : Option<&'r str>,
+}
+
+
+/// The formats of the feeds.
+#[derive(Clone, Copy)]
+enum FeedFormat {
+	Atom,
+	Rss,
+}
+
+
+#[derive(serde::Serialize)]
+struct FeedContext<'a> {
+	board_id   : &'a str,
+	board_name : &'a str,
+	base_url   : String,
+	updated    : String,
+	entries    : Vec<FeedEntry<'a>>,
+}
+
+
+/// The representation of a `Post` in the feeds.
+#[derive(serde::Serialize)]
+struct FeedEntry<'a> {
+	id      : u64,
+	author  : &'a str,
+	title   : String,
+	date    : String,
+	message : &'a str,
+}
+
+impl<'a> FeedEntry<'a> {
+	fn new(p_post: &'a Post, p_format: FeedFormat) -> FeedEntry<'a> {
+		let message = p_post.message();
+		let mut title = format!("{}: {}", p_post.author(), message.chars().take(TITLE_LENGTH).collect::<String>());
+		if message.chars().count() > TITLE_LENGTH {
+			title.push('…');
+		}
+
+		FeedEntry {
+			id      : p_post.id(),
+			author  : p_post.author(),
+			title,
+			date    : format_date(p_post.instant(), p_format),
+			message : p_post.normalized_message(),
+		}
+	}
+}
+
+
+/// Formats a date for a feed: RFC 3339 for Atom, RFC 2822 for RSS.
+fn format_date(p_date: &DateTime<Utc>, p_format: FeedFormat) -> String {
+	match p_format {
+		FeedFormat::Atom => p_date.to_rfc3339(),
+		FeedFormat::Rss  => p_date.to_rfc2822()
+	}
+}
+
+
+/// Builds the feed of a board.
+fn feed(p_board_id: &str, p_base_url: String, p_history: &LockedHistory, p_params: &FeedParams, p_format: FeedFormat) -> Feed {
+	let history = p_history.read().unwrap();
+	let author = p_params.author.map(str::trim).filter(|a| !a.is_empty());
+
+	let entries: Vec<FeedEntry> = history.iter()
+			.rev()
+			.filter(|p| author.is_none_or(|a| p.author().eq_ignore_ascii_case(a)))
+			.map(|p| FeedEntry::new(p, p_format))
+			.collect();
+
+	// The feed is updated by its last post
+	let updated = history.iter()
+			.next_back()
+			.map(|p| *p.instant())
+			.unwrap_or_else(Utc::now);
+
+	let context = FeedContext {
+		board_id   : p_board_id,
+		board_name : history.board_name(),
+		base_url   : p_base_url,
+		updated    : format_date(&updated, p_format),
+		entries,
+	};
+
+	match p_format {
+		FeedFormat::Atom => (ContentType::new("application", "atom+xml"), Template::render("atom", &context)),
+		FeedFormat::Rss  => (ContentType::new("application", "rss+xml"), Template::render("rss", &context))
+	}
+}
+
+
+/// Handles GET requests for the Atom feed of the default board.
+#[get("/feed.atom?<p_params..>")]
+pub fn atom(p_params: FeedParams<'_>, p_boards: &State<BoardRegistry>) -> Feed {
+	feed(p_boards.default_id(), String::new(), p_boards.default_board(), &p_params, FeedFormat::Atom)
+}
+
+
+/// Handles GET requests for the RSS feed of the default board.
+#[get("/feed.rss?<p_params..>")]
+pub fn rss(p_params: FeedParams<'_>, p_boards: &State<BoardRegistry>) -> Feed {
+	feed(p_boards.default_id(), String::new(), p_boards.default_board(), &p_params, FeedFormat::Rss)
+}
+
+
+/// Handles GET requests for the Atom feed of a given board.
+#[get("/b/<p_board>/feed.atom?<p_params..>")]
+pub fn board_atom(p_board: &str, p_params: FeedParams<'_>, p_boards: &State<BoardRegistry>) -> Option<Feed> {
+	p_boards.get(p_board).map(|h| feed(p_board, format!("/b/{}", p_board), h, &p_params, FeedFormat::Atom))
+}
+
+
+/// Handles GET requests for the RSS feed of a given board.
+#[get("/b/<p_board>/feed.rss?<p_params..>")]
+pub fn board_rss(p_board: &str, p_params: FeedParams<'_>, p_boards: &State<BoardRegistry>) -> Option<Feed> {
+	p_boards.get(p_board).map(|h| feed(p_board, format!("/b/{}", p_board), h, &p_params, FeedFormat::Rss))
+}
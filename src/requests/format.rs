@@ -0,0 +1,82 @@
+//! The negotiation of the backend output format.
+
+use rocket::request::{self, FromParam, FromRequest, Outcome, Request};
+
+
+/// The formats a backend can be rendered in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendFormat {
+	/// The traditional XML backend, with the messages in CDATA sections.
+	Xml,
+	/// A JSON document.
+	Json,
+}
+
+impl BackendFormat {
+	/// Gives the format matching a file extension (`xml` or `json`).
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::requests::format::BackendFormat;
+	///
+	/// assert_eq!(BackendFormat::from_extension("json"), Some(BackendFormat::Json));
+	/// assert_eq!(BackendFormat::from_extension("xml"),  Some(BackendFormat::Xml));
+	/// assert_eq!(BackendFormat::from_extension("html"), None);
+	/// ```
+	pub fn from_extension(p_extension: &str) -> Option<BackendFormat> {
+		match p_extension {
+			"xml"  => Some(BackendFormat::Xml),
+			"json" => Some(BackendFormat::Json),
+			_      => None
+		}
+	}
+}
+
+
+/// Selects the format from the `Accept` header of the request.
+///
+/// JSON is chosen when it is the client's preferred media type, XML otherwise.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BackendFormat {
+	type Error = ();
+
+	async fn from_request(p_request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+		let json_preferred = p_request.accept()
+				.map(|a| a.preferred().media_type().is_json())
+				.unwrap_or(false);
+
+		Outcome::Success(if json_preferred { BackendFormat::Json } else { BackendFormat::Xml })
+	}
+}
+
+
+/// A path parameter that may end with a format extension, like `42.json`.
+///
+/// The `format` is only set when the parameter has a known extension; it
+/// takes precedence over the `Accept` header.
+pub struct Formatted<T> {
+	pub value  : T,
+	pub format : Option<BackendFormat>,
+}
+
+impl<T> Formatted<T> {
+	/// Gives the format of the parameter, or `p_default` if it has no extension.
+	pub fn format_or(&self, p_default: BackendFormat) -> BackendFormat {
+		self.format.unwrap_or(p_default)
+	}
+}
+
+impl<'a, T: FromParam<'a>> FromParam<'a> for Formatted<T> {
+	type Error = T::Error;
+
+	fn from_param(p_param: &'a str) -> Result<Self, Self::Error> {
+		if let Some((value, extension)) = p_param.rsplit_once('.') {
+			if let Some(format) = BackendFormat::from_extension(extension) {
+				return T::from_param(value).map(|v| Formatted { value: v, format: Some(format) });
+			}
+		}
+
+		T::from_param(p_param).map(|v| Formatted { value: v, format: None })
+	}
+}
@@ -2,8 +2,19 @@
 /// The handlers for the board's requests.
 
 pub mod index;
+pub mod account;
 pub mod about;
+pub mod archive;
 pub mod board;
 pub mod backend;
+pub mod cache;
+pub mod feed;
+pub mod format;
+pub mod moderation;
 pub mod post;
+pub mod search;
 pub mod clients_config;
+pub mod events;
+pub mod thread;
+pub mod view;
+pub mod websocket;
@@ -0,0 +1,130 @@
+//! The handlers for the moderation of the posts.
+//!
+//! Only the moderators can delete or mask a post. They are authenticated by
+//! an `Authorization: Bearer <token>` header, the tokens being listed in the
+//! `[moderation]` section of the configuration.
+//!
+//! The posts of the history are moderated through the `History`, so that its
+//! listeners record the moderation and tell the clients. The older posts are
+//! only moderated in the storage and the search index.
+
+use crate::config::ModerationParams;
+use crate::core::BoardRegistry;
+use crate::core::history::Moderation;
+use rocket::{delete, post};
+use rocket::State;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Outcome, Request};
+use subtle::ConstantTimeEq;
+
+
+/// A request guard accepting only the moderators.
+///
+/// The token is compared to every configured one in constant time, so that
+/// the response time does not tell how much of it is right. Other requests
+/// fail with a `401 Unauthorized`.
+pub struct Moderator;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Moderator {
+	type Error = ();
+
+	async fn from_request(p_request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+		let token = p_request.headers()
+				.get_one("Authorization")
+				.and_then(|a| a.strip_prefix("Bearer "))
+				.map(str::trim);
+
+		let is_moderator = match (token, p_request.rocket().state::<ModerationParams>()) {
+			(Some(token), Some(params)) => params.tokens.iter()
+					.filter(|t| !t.is_empty())
+					.fold(0u8, |found, t| found | t.as_bytes().ct_eq(token.as_bytes()).unwrap_u8()) == 1,
+			_                           => false
+		};
+
+		if is_moderator {
+			Outcome::Success(Moderator)
+		}
+		else {
+			Outcome::Error((Status::Unauthorized, ()))
+		}
+	}
+}
+
+
+/// Moderates a post of a board.
+///
+/// Returns `204 No Content` once done, or `404 Not Found` if there is no such
+/// board or post.
+fn moderate(p_boards: &BoardRegistry, p_board_id: &str, p_post_id: u64, p_moderation: Moderation) -> Status {
+	let history = match p_boards.get(p_board_id) {
+		Some(history) => history,
+		None          => return Status::NotFound
+	};
+
+	if history.write().unwrap().moderate_post(p_post_id, p_moderation).is_some() {
+		info_msg!("Post #{} of board '{}' moderated ({:?}).", p_post_id, p_board_id, p_moderation);
+		return Status::NoContent;
+	}
+
+	// The post is no longer in the history, look for it in the storage
+	let storage = match p_boards.storage(p_board_id) {
+		Some(storage) => storage,
+		None          => return Status::NotFound
+	};
+
+	let post = match storage.posts_between(p_post_id, p_post_id) {
+		Ok(mut posts) => match posts.pop() {
+			Some(post) => post,
+			None       => return Status::NotFound
+		},
+		Err(e) => {
+			warn_msg!("Failed to read post #{} of board '{}': {}", p_post_id, p_board_id, e);
+			return Status::InternalServerError;
+		}
+	};
+
+	if let Err(e) = storage.moderate_post(p_post_id, p_moderation) {
+		warn_msg!("Failed to record the moderation of post #{} of board '{}': {}", p_post_id, p_board_id, e);
+		return Status::InternalServerError;
+	}
+
+	if let Some(search) = p_boards.search(p_board_id) {
+		let moderated = match p_moderation {
+			Moderation::Delete => post,
+			Moderation::Mask   => post.masked()
+		};
+		search.write().unwrap().moderate(&moderated, p_moderation);
+	}
+
+	info_msg!("Archived post #{} of board '{}' moderated ({:?}).", p_post_id, p_board_id, p_moderation);
+	Status::NoContent
+}
+
+
+/// Handles DELETE requests for a post of the default board.
+#[delete("/post/<p_post_id>")]
+pub fn delete(p_post_id: u64, _p_moderator: Moderator, p_boards: &State<BoardRegistry>) -> Status {
+	moderate(p_boards, p_boards.default_id(), p_post_id, Moderation::Delete)
+}
+
+
+/// Handles DELETE requests for a post of a given board.
+#[delete("/b/<p_board>/post/<p_post_id>")]
+pub fn board_delete(p_board: &str, p_post_id: u64, _p_moderator: Moderator, p_boards: &State<BoardRegistry>) -> Status {
+	moderate(p_boards, p_board, p_post_id, Moderation::Delete)
+}
+
+
+/// Handles POST requests to mask a post of the default board.
+#[post("/post/<p_post_id>/mask")]
+pub fn mask(p_post_id: u64, _p_moderator: Moderator, p_boards: &State<BoardRegistry>) -> Status {
+	moderate(p_boards, p_boards.default_id(), p_post_id, Moderation::Mask)
+}
+
+
+/// Handles POST requests to mask a post of a given board.
+#[post("/b/<p_board>/post/<p_post_id>/mask")]
+pub fn board_mask(p_board: &str, p_post_id: u64, _p_moderator: Moderator, p_boards: &State<BoardRegistry>) -> Status {
+	moderate(p_boards, p_board, p_post_id, Moderation::Mask)
+}
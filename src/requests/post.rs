@@ -1,13 +1,17 @@
-use crate::core::{LockedHistory, UserPost};
+use crate::core::{BoardRegistry, LockedHistory, RateLimiter, UserPost};
+use crate::core::limiter::LimitKind;
+use crate::requests::account::Session;
 use rocket::post;
 use rocket::State;
 use rocket::form::{Form, FromForm};
 use rocket::http::Status;
 use rocket::request::{self, Request, FromRequest, Outcome};
 use rocket::response::{self, Response, Responder};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
 
 
-pub struct UserAgent<'r>(Option<&'r str>);
+pub struct UserAgent<'r>(pub Option<&'r str>);
 
 #[derive(Debug)]
 pub enum UserAgentError {
@@ -24,9 +28,12 @@ impl<'r> FromRequest<'r> for UserAgent<'r> {
 }
 
 
+/// The form of a new post.
+///
+/// The login is not part of it: it is given by the `Session` of the user.
+/// A `login` field sent by an older client is ignored.
 #[derive(FromForm)]
 pub struct Message {
-	login: String,
 	message: String
 }
 
@@ -43,22 +50,150 @@ impl<'r> Responder<'r, 'static> for PostId {
 }
 
 
-#[post("/post", data="<p_message>")]
-pub fn form(p_message: Form<Message>, p_user_agent: UserAgent<'_>, p_history: &State<LockedHistory>) -> PostId {
-	let mut history = p_history.write().unwrap();
+/// The longest wait advised to a client posting too fast, in seconds.
+const MAX_RETRY_AFTER: u64 = 3600;
+
+
+/// The answer to a client posting too fast: a `429 Too Many Requests` with
+/// a `Retry-After` header giving the seconds to wait.
+pub struct TooManyRequests(pub Duration);
+
+impl TooManyRequests {
+	/// Gives the seconds to wait, rounded up, an hour at most.
+	pub fn retry_after(&self) -> u64 {
+		self.0.as_secs()
+				.saturating_add(u64::from(self.0.subsec_nanos() > 0))
+				.min(MAX_RETRY_AFTER)
+	}
+}
+
+impl<'r> Responder<'r, 'static> for TooManyRequests {
+	fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+		Response::build()
+			.raw_header("Retry-After", self.retry_after().to_string())
+			.status(Status::TooManyRequests)
+			.ok()
+	}
+}
+
+
+/// The author of a post: the login of its session, its IP address and its User-Agent.
+pub struct Author {
+	pub session    : Session,
+	pub ip         : Option<IpAddr>,
+	pub user_agent : Option<String>,
+}
+
+impl Author {
+	/// Builds the `UserPost` of a message of the author.
+	pub fn user_post(&self, p_message: &str) -> UserPost {
+		user_post(self.session.login(), p_message, self.user_agent.as_deref())
+	}
+
+
+	/// Takes a token from the rate limiter for a post of the author.
+	///
+	/// The author is identified by its IP address, its login if it is
+	/// authenticated and its User-Agent.
+	pub fn check_rate(&self, p_limiter: &RateLimiter) -> Result<(), TooManyRequests> {
+		let ip = self.ip.map(|ip| ip.to_string());
+
+		let mut keys = Vec::new();
+		if let Some(ip) = &ip {
+			keys.push((LimitKind::Ip, ip.as_str()));
+		}
+		if !self.session.login().is_empty() {
+			keys.push((LimitKind::Login, self.session.login()));
+		}
+		if let Some(user_agent) = &self.user_agent {
+			keys.push((LimitKind::UserAgent, user_agent.as_str()));
+		}
+
+		p_limiter.acquire(&keys, Instant::now()).map_err(TooManyRequests)
+	}
+
+
+	/// Takes a token from the rate limiter for an attempt of the author to
+	/// log in or to register.
+	///
+	/// The author is only identified by its IP address, the login being the
+	/// one it tries.
+	pub fn check_account_rate(&self, p_limiter: &RateLimiter) -> Result<(), TooManyRequests> {
+		let ip = self.ip.map(|ip| ip.to_string());
+		let keys: Vec<(LimitKind, &str)> = ip.iter().map(|ip| (LimitKind::Account, ip.as_str())).collect();
+		p_limiter.acquire(&keys, Instant::now()).map_err(TooManyRequests)
+	}
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Author {
+	type Error = ();
 
+	async fn from_request(p_request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+		let session = match p_request.guard::<Session>().await {
+			Outcome::Success(session) => session,
+			_                         => Session(None)
+		};
+
+		Outcome::Success(Author {
+			session,
+			ip         : p_request.client_ip(),
+			user_agent : p_request.headers().get_one("User-Agent").map(String::from),
+		})
+	}
+}
+
+
+/// Builds the `UserPost` of a message sent by a client.
+///
+/// The fields are trimmed. The User-Agent is truncated to 80 characters, or
+/// replaced by "Anonymous Coward" if it is missing.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::requests::post::user_post;
+///
+/// let user_agent = "é".repeat(100);
+/// assert_eq!(user_post("", "Plop!", Some(&user_agent)).user_agent.chars().count(), 80);
+/// assert_eq!(user_post("", "Plop!", None).user_agent, "Anonymous Coward");
+/// ```
+pub fn user_post(p_login: &str, p_message: &str, p_user_agent: Option<&str>) -> UserPost {
 	// Process the User-Agent
-	let mut user_agent = match p_user_agent.0 {
+	let mut user_agent = match p_user_agent {
 		Some(ua) => ua.trim().to_string(),
 		None     => String::from("Anonymous Coward")
 	};
-	user_agent.truncate(80);
-	
-	PostId(
-		history.add_post(
-			UserPost {
-				login     : p_message.login.trim().to_string(),
-				user_agent: user_agent,
-				message   : p_message.message.trim().to_string()
-			}))
+	if let Some((end, _)) = user_agent.char_indices().nth(80) {
+		user_agent.truncate(end);
+	}
+
+	UserPost {
+		login     : p_login.trim().to_string(),
+		user_agent,
+		message   : p_message.trim().to_string()
+	}
+}
+
+
+/// Adds the posted message to the given board, signed with the login of the session.
+///
+/// The post is refused if the author has exceeded its rate.
+fn add_post(p_history: &LockedHistory, p_message: &Message, p_author: &Author, p_limiter: &RateLimiter) -> Result<PostId, TooManyRequests> {
+	p_author.check_rate(p_limiter)?;
+
+	let mut history = p_history.write().unwrap();
+	Ok(PostId(history.add_post(p_author.user_post(&p_message.message))))
+}
+
+
+#[post("/post", data="<p_message>")]
+pub fn form(p_message: Form<Message>, p_author: Author, p_limiter: &State<RateLimiter>, p_boards: &State<BoardRegistry>) -> Result<PostId, TooManyRequests> {
+	add_post(p_boards.default_board(), &p_message, &p_author, p_limiter)
+}
+
+
+#[post("/b/<p_board>/post", data="<p_message>")]
+pub fn board_form(p_board: &str, p_message: Form<Message>, p_author: Author, p_limiter: &State<RateLimiter>, p_boards: &State<BoardRegistry>) -> Option<Result<PostId, TooManyRequests>> {
+	p_boards.get(p_board).map(|h| add_post(h, &p_message, &p_author, p_limiter))
 }
@@ -0,0 +1,115 @@
+//! The handlers for the full-text search.
+//!
+//! The results are rendered like the backend, in XML or JSON depending on
+//! the `Accept` header, the most recent post first.
+
+use crate::core::{BoardRegistry, Post};
+use crate::core::search::SearchQuery;
+use crate::requests::archive::ArchiveDay;
+use crate::requests::backend::{self, Backend};
+use crate::requests::format::BackendFormat;
+use rocket::{get, FromForm};
+use rocket::State;
+use rocket::http::Status;
+use rocket::request::FromParam;
+use std::collections::HashMap;
+
+
+/// The number of results given when the request has no `limit`.
+pub const DEFAULT_LIMIT: usize = 100;
+
+/// The maximum number of results of a search.
+pub const MAX_LIMIT: usize = 1000;
+
+
+/// The parameters of a search request.
+///
+/// The days are formatted as `YYYY-mm-dd`, like in the archive URLs.
+#[derive(FromForm)]
+pub struct SearchParams<'r> {
+	/// The words to look for
+	#[field(default = "")]
+	q      : &'r str,
+	/// The login, or the user agent for anonymous posts, of the author
+	author : Option<&'r str>,
+	/// The first day of the posts
+	from   : Option<&'r str>,
+	/// The last day of the posts
+	to     : Option<&'r str>,
+	/// The board to search, the default one if not given
+	board  : Option<&'r str>,
+	/// The maximum number of results
+	limit  : Option<usize>,
+}
+
+impl<'r> SearchParams<'r> {
+	/// Builds the query of the search.
+	///
+	/// Fails with a `400 Bad Request` if a day is invalid.
+	fn query(&self) -> Result<SearchQuery, Status> {
+		let mut query = SearchQuery::new(self.q, self.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT));
+		query.author = self.author.filter(|a| !a.trim().is_empty()).map(String::from);
+		query.from   = self.from.map(parse_day).transpose()?;
+		query.to     = self.to.map(parse_day).transpose()?;
+		Ok(query)
+	}
+}
+
+
+/// Converts a `YYYY-mm-dd` day into the "YYYYmmdd" format of the posts.
+fn parse_day(p_day: &str) -> Result<String, Status> {
+	ArchiveDay::from_param(p_day)
+		.map(|d| d.as_str().to_string())
+		.map_err(|_| Status::BadRequest)
+}
+
+
+/// Searches the posts of a board.
+///
+/// The matching posts still in the history are taken from it, the other ones
+/// are read from the storage of the board.
+///
+/// Returns a `404 Not Found` if there is no such board.
+fn search(p_board_id: &str, p_params: &SearchParams, p_format: BackendFormat, p_boards: &BoardRegistry) -> Result<Backend, Status> {
+	let history = p_boards.get(p_board_id).ok_or(Status::NotFound)?;
+	let index = p_boards.search(p_board_id).ok_or(Status::NotFound)?;
+	let query = p_params.query()?;
+
+	// The index lock is released before taking the history one
+	let ids = index.read().unwrap().search(&query);
+
+	let history = history.read().unwrap();
+	let mut posts: HashMap<u64, Post> = history.iter()
+			.filter(|p| ids.contains(&p.id()))
+			.map(|p| (p.id(), p.clone()))
+			.collect();
+
+	let archived: Vec<u64> = ids.iter().copied().filter(|id| !posts.contains_key(id)).collect();
+	if let (Some(storage), false) = (p_boards.storage(p_board_id), archived.is_empty()) {
+		let stored = storage.posts_with_ids(&archived).map_err(|e| {
+			warn_msg!("Failed to read the archives of board '{}' from '{}': {}", p_board_id, storage.location(), e);
+			Status::InternalServerError
+		})?;
+		posts.extend(stored.iter().map(|p| (p.id(), p.in_timezone(history.timezone()))));
+	}
+
+	let posts: Vec<Post> = ids.iter().filter_map(|id| posts.remove(id)).collect();
+	Ok(backend::render(p_board_id, &history, posts.iter(), p_format))
+}
+
+
+/// Handles GET requests for a search.
+///
+/// The default board is searched, unless a `board` parameter is given.
+#[get("/search?<p_params..>")]
+pub fn posts(p_params: SearchParams<'_>, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Result<Backend, Status> {
+	let board_id = p_params.board.unwrap_or(p_boards.default_id());
+	search(board_id, &p_params, p_format, p_boards)
+}
+
+
+/// Handles GET requests for a search in a given board.
+#[get("/b/<p_board>/search?<p_params..>")]
+pub fn board_posts(p_board: &str, p_params: SearchParams<'_>, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Result<Backend, Status> {
+	search(p_board, &p_params, p_format, p_boards)
+}
@@ -0,0 +1,51 @@
+//! The handlers for the threads of posts, built from their norloges.
+//!
+//! The answers are rendered like the backend, in XML or JSON depending on
+//! the `Accept` header, the most recent post first.
+
+use crate::core::{BoardRegistry, History, LockedHistory, Post};
+use crate::requests::backend::{self, Backend};
+use crate::requests::format::BackendFormat;
+use rocket::get;
+use rocket::State;
+
+
+/// Renders the posts linked to a post of a board.
+///
+/// Returns `None` if the post is not in the history.
+fn linked_posts<F>(p_board_id: &str, p_history: &LockedHistory, p_post_id: u64, p_format: BackendFormat, p_links: F) -> Option<Backend>
+		where F: for<'a> Fn(&'a History, u64) -> Vec<&'a Post> {
+	let history = p_history.read().unwrap();
+	history.get(p_post_id)?;
+
+	let posts = p_links(&history, p_post_id);
+	Some(backend::render(p_board_id, &history, posts.into_iter().rev(), p_format))
+}
+
+
+/// Handles GET requests for the answers to a post.
+#[get("/post/<p_post_id>/replies")]
+pub fn replies(p_post_id: u64, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Option<Backend> {
+	linked_posts(p_boards.default_id(), p_boards.default_board(), p_post_id, p_format, |h, id| h.replies(id))
+}
+
+
+/// Handles GET requests for the answers to a post of a given board.
+#[get("/b/<p_board>/post/<p_post_id>/replies")]
+pub fn board_replies(p_board: &str, p_post_id: u64, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Option<Backend> {
+	linked_posts(p_board, p_boards.get(p_board)?, p_post_id, p_format, |h, id| h.replies(id))
+}
+
+
+/// Handles GET requests for the posts referenced by a post.
+#[get("/post/<p_post_id>/references")]
+pub fn references(p_post_id: u64, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Option<Backend> {
+	linked_posts(p_boards.default_id(), p_boards.default_board(), p_post_id, p_format, |h, id| h.references(id))
+}
+
+
+/// Handles GET requests for the posts referenced by a post of a given board.
+#[get("/b/<p_board>/post/<p_post_id>/references")]
+pub fn board_references(p_board: &str, p_post_id: u64, p_format: BackendFormat, p_boards: &State<BoardRegistry>) -> Option<Backend> {
+	linked_posts(p_board, p_boards.get(p_board)?, p_post_id, p_format, |h, id| h.references(id))
+}
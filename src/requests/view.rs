@@ -0,0 +1,39 @@
+//! The view models shared by the handlers.
+
+use crate::core::Post;
+use chrono::SecondsFormat;
+
+
+/// The representation of a `Post` in the backends and the streams.
+///
+/// The `message` is the normalized one, safe to insert in an HTML page. The
+/// `raw_message` is the message as sent by its author. The `instant` is the
+/// UTC datetime of the post, as RFC 3339, whereas the `time` is its clock in
+/// the timezone of the board. The `index` tells the posts of a same second
+/// apart, starting at 1.
+#[derive(serde::Serialize)]
+pub struct PostViewModel<'a> {
+	pub id          : u64,
+	pub time        : &'a str,
+	pub instant     : String,
+	pub index       : u32,
+	pub user_agent  : &'a str,
+	pub message     : &'a str,
+	pub raw_message : &'a str,
+	pub login       : &'a str,
+}
+
+impl<'a> PostViewModel<'a> {
+	pub fn new(p_post: &Post) -> PostViewModel<'_> {
+		PostViewModel {
+			id          : p_post.id(),
+			time        : p_post.time(),
+			instant     : p_post.instant().to_rfc3339_opts(SecondsFormat::Millis, true),
+			index       : p_post.index(),
+			user_agent  : p_post.user_agent(),
+			message     : p_post.normalized_message(),
+			raw_message : p_post.message(),
+			login       : p_post.login(),
+		}
+	}
+}
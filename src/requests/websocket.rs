@@ -0,0 +1,295 @@
+//! The WebSocket interface to read and post messages.
+//!
+//! The client and the server exchange JSON objects in text frames, the
+//! `type` field telling what the object is.
+//!
+//! The client can send:
+//!
+//! * `{"type": "subscribe", "since": 42}` - starts receiving the new posts.
+//!   If `since` is given, the posts of the history newer than this id are
+//!   sent first.
+//! * `{"type": "post", "message": "Plop!", "ref": 1}` - adds a post to the
+//!   board, signed with the login of the session of the connection. `ref` is
+//!   optional.
+//!
+//! The server sends:
+//!
+//! * `{"type": "post", "post": {...}}` - a post, once subscribed.
+//! * `{"type": "delete", "post": {...}}` - a post deleted by a moderator, once subscribed.
+//! * `{"type": "mask", "post": {...}}` - the masked copy of a post, once subscribed.
+//! * `{"type": "ack", "id": 43, "ref": 1}` - the id given to a post sent by
+//!   the client, along with the `ref` of its request.
+//! * `{"type": "error", "message": "...", "ref": 1}` - a request could not
+//!   be processed. When the client posts too fast, the error also has a
+//!   `retry_after` field giving the seconds to wait.
+//!
+//! As the connections are authenticated by the session cookie, which the
+//! browsers send whatever the page opening the connection, only the pages of
+//! the board itself may open one: see `same_origin()`.
+
+use crate::core::{BoardRegistry, LockedHistory, PostBroadcaster, RateLimiter};
+use crate::core::broadcast::BoardEvent;
+use crate::core::history::Moderation;
+use crate::requests::post::Author;
+use crate::requests::view::PostViewModel;
+use rocket::{get, Shutdown, State};
+use rocket::data::{IoHandler, IoStream};
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{self, json, Value};
+use rocket::tokio::select;
+use std::future;
+use std::io;
+use std::pin::Pin;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+
+
+/// The requests a client can send.
+#[derive(serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientRequest {
+	Subscribe {
+		since : Option<u64>,
+	},
+	Post {
+		message   : String,
+		#[serde(rename = "ref")]
+		reference : Option<Value>,
+	},
+}
+
+
+/// The accept key of a WebSocket handshake.
+///
+/// It is derived from the `Sec-WebSocket-Key` header. Requests which are not
+/// WebSocket handshakes fail with a `400 Bad Request`, and the handshakes
+/// coming from the pages of another site fail with a `403 Forbidden`.
+pub struct WebSocketKey(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WebSocketKey {
+	type Error = ();
+
+	async fn from_request(p_request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+		let headers = p_request.headers();
+		let is_websocket = headers.get("Upgrade").any(|u| u.eq_ignore_ascii_case("websocket"))
+				&& headers.get_one("Sec-WebSocket-Version") == Some("13");
+
+		match headers.get_one("Sec-WebSocket-Key") {
+			Some(_) if is_websocket && !same_origin(headers.get_one("Origin"), headers.get_one("Host")) => {
+				warn_msg!("WebSocket connection from origin '{}' refused.", headers.get_one("Origin").unwrap_or_default());
+				Outcome::Error((Status::Forbidden, ()))
+			},
+			Some(key) if is_websocket => Outcome::Success(WebSocketKey(derive_accept_key(key.as_bytes()))),
+			_                         => Outcome::Error((Status::BadRequest, ()))
+		}
+	}
+}
+
+
+/// Tells whether a WebSocket handshake comes from a page of the board.
+///
+/// The browsers give the page opening the connection in the `Origin` header:
+/// its host (and port) must be the `Host` the request was sent to. The
+/// clients which are not browsers send no `Origin`, and are accepted.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::requests::websocket::same_origin;
+///
+/// assert!(same_origin(Some("https://board.example.com"), Some("board.example.com")));
+/// assert!(same_origin(Some("http://localhost:8000"), Some("localhost:8000")));
+/// assert!(same_origin(None, Some("board.example.com")));
+///
+/// assert!(!same_origin(Some("https://evil.example.org"), Some("board.example.com")));
+/// assert!(!same_origin(Some("http://localhost:8001"), Some("localhost:8000")));
+/// assert!(!same_origin(Some("null"), Some("board.example.com")));
+/// assert!(!same_origin(Some("https://board.example.com"), None));
+/// ```
+pub fn same_origin(p_origin: Option<&str>, p_host: Option<&str>) -> bool {
+	let origin = match p_origin {
+		Some(origin) => origin,
+		None         => return true
+	};
+
+	let origin_host = origin.strip_prefix("https://").or_else(|| origin.strip_prefix("http://"));
+	match (origin_host, p_host) {
+		(Some(origin_host), Some(host)) => origin_host.trim_end_matches('/').eq_ignore_ascii_case(host.trim()),
+		_                               => false
+	}
+}
+
+
+/// A WebSocket connection to a board.
+pub struct BoardSocket<'r> {
+	accept_key  : String,
+	history     : &'r LockedHistory,
+	broadcaster : &'r PostBroadcaster,
+	limiter     : &'r RateLimiter,
+	author      : Author,
+	shutdown    : Shutdown,
+}
+
+impl<'r> BoardSocket<'r> {
+	/// Processes a request of the client.
+	///
+	/// Returns the messages to send back.
+	fn process(&self, p_request: &str, p_receiver: &mut Option<Receiver<BoardEvent>>, p_last_id: &mut u64) -> Vec<Value> {
+		match json::from_str(p_request) {
+			Ok(ClientRequest::Subscribe { since }) => {
+				// Subscribe before reading the history so that no post can be missed in between
+				*p_receiver = Some(self.broadcaster.subscribe());
+				match since {
+					Some(id) => {
+						*p_last_id = id;
+						self.missed_posts(p_last_id)
+					},
+					None => {
+						*p_last_id = self.history.read().unwrap().iter().last().map(|p| p.id()).unwrap_or(0);
+						Vec::new()
+					}
+				}
+			},
+			Ok(ClientRequest::Post { message, reference }) => {
+				if let Err(e) = self.author.check_rate(self.limiter) {
+					return vec![ json!({ "type": "error", "message": "Too many posts", "retry_after": e.retry_after(), "ref": reference }) ];
+				}
+
+				let post_id = self.history.write().unwrap()
+						.add_post(self.author.user_post(&message));
+				vec![ json!({ "type": "ack", "id": post_id, "ref": reference }) ]
+			},
+			Err(e) => vec![ json!({ "type": "error", "message": e.to_string() }) ]
+		}
+	}
+
+
+	/// Collects the posts of the history newer than `p_last_id`.
+	///
+	/// `p_last_id` is updated to the id of the last collected post.
+	fn missed_posts(&self, p_last_id: &mut u64) -> Vec<Value> {
+		let history = self.history.read().unwrap();
+
+		let since = *p_last_id;
+		let mut posts = Vec::new();
+		for post in history.iter().filter(|p| p.id() > since) {
+			posts.push(post_message(&PostViewModel::new(post)));
+			*p_last_id = post.id();
+		}
+		posts
+	}
+}
+
+
+/// Builds the message of a post.
+fn post_message(p_post: &PostViewModel) -> Value {
+	json!({ "type": "post", "post": p_post })
+}
+
+
+/// Builds the message of a moderated post.
+fn moderation_message(p_post: &PostViewModel, p_moderation: Moderation) -> Value {
+	let kind = match p_moderation {
+		Moderation::Delete => "delete",
+		Moderation::Mask   => "mask",
+	};
+	json!({ "type": kind, "post": p_post })
+}
+
+
+/// Waits for the next event of a subscription, forever if there is none.
+async fn next_event(p_receiver: &mut Option<Receiver<BoardEvent>>) -> Result<BoardEvent, RecvError> {
+	match p_receiver {
+		Some(receiver) => receiver.recv().await,
+		None           => future::pending().await
+	}
+}
+
+
+#[rocket::async_trait]
+impl<'r> IoHandler for BoardSocket<'r> {
+	async fn io(self: Pin<Box<Self>>, p_io: IoStream) -> io::Result<()> {
+		let socket = WebSocketStream::from_raw_socket(p_io, Role::Server, None).await;
+		let (mut sink, mut stream) = socket.split();
+		let mut shutdown = self.shutdown.clone();
+		let mut receiver = None;
+		let mut last_id = 0;
+
+		loop {
+			let replies = select! {
+				frame = stream.next() => match frame {
+					Some(Ok(Message::Text(text))) => self.process(text.as_str(), &mut receiver, &mut last_id),
+					Some(Ok(Message::Close(_))) | None => break,
+					Some(Ok(_))  => Vec::new(),
+					Some(Err(e)) => return Err(io::Error::other(e)),
+				},
+				event = next_event(&mut receiver) => match event {
+					Ok(BoardEvent::PostAdded(post)) if post.id() > last_id => {
+						last_id = post.id();
+						vec![ post_message(&PostViewModel::new(&post)) ]
+					},
+					Ok(BoardEvent::PostDeleted(post, moderation)) => vec![ moderation_message(&PostViewModel::new(&post), moderation) ],
+					Ok(_) => Vec::new(),
+					// Some events were dropped, read them from the history
+					Err(RecvError::Lagged(_)) => self.missed_posts(&mut last_id),
+					Err(RecvError::Closed)    => break,
+				},
+				_ = &mut shutdown => break,
+			};
+
+			for reply in replies {
+				sink.send(Message::text(reply.to_string())).await.map_err(io::Error::other)?;
+			}
+		}
+
+		// The connection may already be closed, so the result does not matter
+		let _ = sink.close().await;
+		Ok(())
+	}
+}
+
+
+impl<'r, 'o: 'r> Responder<'r, 'o> for BoardSocket<'o> {
+	fn respond_to(self, _: &'r Request<'_>) -> response::Result<'o> {
+		Response::build()
+			.raw_header("Sec-WebSocket-Accept", self.accept_key.clone())
+			.upgrade("websocket", self)
+			.ok()
+	}
+}
+
+
+/// Handles the WebSocket connections to the default board.
+#[get("/ws")]
+pub fn connect<'r>(p_key: WebSocketKey, p_author: Author, p_limiter: &'r State<RateLimiter>, p_boards: &'r State<BoardRegistry>, p_end: Shutdown) -> BoardSocket<'r> {
+	BoardSocket {
+		accept_key  : p_key.0,
+		history     : p_boards.default_board(),
+		broadcaster : p_boards.default_broadcaster(),
+		limiter     : p_limiter,
+		author      : p_author,
+		shutdown    : p_end,
+	}
+}
+
+
+/// Handles the WebSocket connections to a given board.
+#[get("/b/<p_board>/ws")]
+pub fn board_connect<'r>(p_board: &str, p_key: WebSocketKey, p_author: Author, p_limiter: &'r State<RateLimiter>, p_boards: &'r State<BoardRegistry>, p_end: Shutdown) -> Option<BoardSocket<'r>> {
+	Some(BoardSocket {
+		accept_key  : p_key.0,
+		history     : p_boards.get(p_board)?,
+		broadcaster : p_boards.broadcaster(p_board)?,
+		limiter     : p_limiter,
+		author      : p_author,
+		shutdown    : p_end,
+	})
+}
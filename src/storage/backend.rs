@@ -1,5 +1,61 @@
 use crate::core::{History, Post};
+use crate::core::history::Moderation;
+use std::collections::HashSet;
+use std::fmt;
 use std::io;
+use std::sync::Arc;
+
+
+/// What was repaired in a storage after a crash.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RepairReport {
+	/// The number of bytes of the incomplete records cut at the end of the files
+	pub truncated_bytes  : usize,
+	/// The number of corrupted records removed from the files
+	pub quarantined      : usize,
+	/// The files the removed data were moved to
+	pub quarantine_files : Vec<String>,
+}
+
+impl RepairReport {
+	/// Tells whether nothing had to be repaired.
+	pub fn is_clean(&self) -> bool {
+		self.truncated_bytes == 0 && self.quarantined == 0
+	}
+
+
+	/// Adds the repairs of another report to this one.
+	pub fn merge(&mut self, p_other: RepairReport) {
+		self.truncated_bytes += p_other.truncated_bytes;
+		self.quarantined += p_other.quarantined;
+		self.quarantine_files.extend(p_other.quarantine_files);
+	}
+}
+
+impl fmt::Display for RepairReport {
+	fn fmt(&self, p_formatter: &mut fmt::Formatter) -> fmt::Result {
+		write!(p_formatter, "{} bytes of incomplete records truncated, {} corrupted records quarantined",
+				self.truncated_bytes, self.quarantined)?;
+		if !self.quarantine_files.is_empty() {
+			write!(p_formatter, " in '{}'", self.quarantine_files.join("', '"))?;
+		}
+		Ok(())
+	}
+}
+
+
+/// The state of a board, stored apart from its posts.
+///
+/// The sequence of the post ids cannot be restored from the posts alone: the
+/// last posts may have been deleted, pruned or rotated out of the files read
+/// at startup, and their ids must never be given again.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BoardState {
+	/// The id the next post will be given
+	pub next_post_id : u64,
+	/// The name of the board
+	pub board_name   : String,
+}
 
 
 /// Common interface for storage backends.
@@ -7,9 +63,327 @@ use std::io;
 /// This is the trait to implement if you want to add another format to save the `History`.
 pub trait StorageBackend {
 	/// Saves a post.
-	fn save_post(&self, p_post: &Post) -> io::Result<&Self>;
+	fn save_post(&self, p_post: &Post) -> io::Result<()>;
+
+
+	/// Saves several posts at once, in order.
+	///
+	/// Backends should override it to commit the posts together.
+	fn save_posts(&self, p_posts: &[Post]) -> io::Result<()> {
+		p_posts.iter().try_for_each(|p| self.save_post(p))
+	}
+
+
+	/// Makes the saved data durable, flushing them to the disk.
+	///
+	/// Does nothing by default, for the backends which are always durable.
+	fn sync(&self) -> io::Result<()> {
+		Ok(())
+	}
+
+
+	/// Records the moderation of a post.
+	///
+	/// The posts read afterwards reflect it: a deleted post is no longer read
+	/// and a masked one is read with its masked message.
+	fn moderate_post(&self, p_post_id: u64, p_moderation: Moderation) -> io::Result<()>;
+
+
+	/// Checks the stored data, repairing the damages of a crash.
+	///
+	/// This is meant to be called at startup, before reading the data. The
+	/// removed data are kept aside. Backends which cannot be damaged do
+	/// nothing, which is the default.
+	fn repair(&self) -> io::Result<RepairReport> {
+		Ok(RepairReport::default())
+	}
+
+
+	/// Frees the space of the deleted posts, returning the number of removed
+	/// records.
+	///
+	/// Does nothing by default, for the backends which delete the posts at once.
+	fn compact(&self) -> io::Result<usize> {
+		Ok(0)
+	}
+
+
+	/// Saves the state of the board, replacing the previous one.
+	///
+	/// Does nothing by default, for the backends which do not store it.
+	fn save_state(&self, _p_state: &BoardState) -> io::Result<()> {
+		Ok(())
+	}
+
+
+	/// Loads the stored state of the board, if any.
+	fn load_state(&self) -> io::Result<Option<BoardState>> {
+		Ok(None)
+	}
 
 
 	/// Loads a stored history.
+	///
+	/// Backends may only load the last posts that fit in the history.
+	/// Returns the number of posts read from the storage.
 	fn load_history(&self, p_history: &mut History) -> io::Result<usize>;
+
+
+	/// Reads the stored posts whose id is between `p_first_id` and `p_last_id`
+	/// (both included), in id order.
+	///
+	/// Unlike `load_history()`, this reads the whole storage and not only the
+	/// posts still in the history.
+	fn posts_between(&self, p_first_id: u64, p_last_id: u64) -> io::Result<Vec<Post>>;
+
+
+	/// Reads the stored posts of a day, given as "YYYYmmdd", in id order.
+	fn posts_of_day(&self, p_day: &str) -> io::Result<Vec<Post>>;
+
+
+	/// Reads the stored posts with the given ids, in id order. The ids of
+	/// the posts which are not stored are ignored.
+	///
+	/// By default, every post is read and only the requested ones are kept.
+	fn posts_with_ids(&self, p_ids: &[u64]) -> io::Result<Vec<Post>> {
+		let ids: HashSet<u64> = p_ids.iter().copied().collect();
+		let mut posts = Vec::new();
+		self.for_each_post(&mut |post| if ids.contains(&post.id()) {
+			posts.push(post);
+		})?;
+		Ok(posts)
+	}
+
+
+	/// Passes every stored post to `p_action`, in id order.
+	///
+	/// Backends should override it to read the posts one at a time, as the
+	/// default reads them all at once.
+	fn for_each_post(&self, p_action: &mut dyn FnMut(Post)) -> io::Result<()> {
+		self.posts_between(1, u64::MAX)?.into_iter().for_each(p_action);
+		Ok(())
+	}
+
+
+	/// Removes the stored posts older than a day, given as "YYYYmmdd".
+	///
+	/// The posts are deleted like moderated ones, so that their ids are never
+	/// given again. Returns the number of removed posts.
+	fn prune_before(&self, p_day: &str) -> io::Result<usize> {
+		let mut count = 0;
+		for post in self.posts_between(1, u64::MAX)?.iter().filter(|p| p.time().as_str() < p_day) {
+			self.moderate_post(post.id(), Moderation::Delete)?;
+			count += 1;
+		}
+		Ok(count)
+	}
+
+
+	/// Describes where the data are stored, for the log messages.
+	fn location(&self) -> String;
+}
+
+
+/// Boxed backends, like the ones returned by `storage::open()`, are backends too.
+impl<T: StorageBackend + ?Sized> StorageBackend for Box<T> {
+	fn save_post(&self, p_post: &Post) -> io::Result<()> {
+		(**self).save_post(p_post)
+	}
+
+
+	fn save_posts(&self, p_posts: &[Post]) -> io::Result<()> {
+		(**self).save_posts(p_posts)
+	}
+
+
+	fn sync(&self) -> io::Result<()> {
+		(**self).sync()
+	}
+
+
+	fn moderate_post(&self, p_post_id: u64, p_moderation: Moderation) -> io::Result<()> {
+		(**self).moderate_post(p_post_id, p_moderation)
+	}
+
+
+	fn repair(&self) -> io::Result<RepairReport> {
+		(**self).repair()
+	}
+
+
+	fn compact(&self) -> io::Result<usize> {
+		(**self).compact()
+	}
+
+
+	fn save_state(&self, p_state: &BoardState) -> io::Result<()> {
+		(**self).save_state(p_state)
+	}
+
+
+	fn load_state(&self) -> io::Result<Option<BoardState>> {
+		(**self).load_state()
+	}
+
+
+	fn load_history(&self, p_history: &mut History) -> io::Result<usize> {
+		(**self).load_history(p_history)
+	}
+
+
+	fn posts_between(&self, p_first_id: u64, p_last_id: u64) -> io::Result<Vec<Post>> {
+		(**self).posts_between(p_first_id, p_last_id)
+	}
+
+
+	fn posts_of_day(&self, p_day: &str) -> io::Result<Vec<Post>> {
+		(**self).posts_of_day(p_day)
+	}
+
+
+	fn posts_with_ids(&self, p_ids: &[u64]) -> io::Result<Vec<Post>> {
+		(**self).posts_with_ids(p_ids)
+	}
+
+
+	fn for_each_post(&self, p_action: &mut dyn FnMut(Post)) -> io::Result<()> {
+		(**self).for_each_post(p_action)
+	}
+
+
+	fn prune_before(&self, p_day: &str) -> io::Result<usize> {
+		(**self).prune_before(p_day)
+	}
+
+
+	fn location(&self) -> String {
+		(**self).location()
+	}
+}
+
+
+/// Shared backends, read by the archive routes while a `HistoryRecorder`
+/// writes to them, are backends too.
+impl<T: StorageBackend + ?Sized> StorageBackend for Arc<T> {
+	fn save_post(&self, p_post: &Post) -> io::Result<()> {
+		(**self).save_post(p_post)
+	}
+
+
+	fn save_posts(&self, p_posts: &[Post]) -> io::Result<()> {
+		(**self).save_posts(p_posts)
+	}
+
+
+	fn sync(&self) -> io::Result<()> {
+		(**self).sync()
+	}
+
+
+	fn moderate_post(&self, p_post_id: u64, p_moderation: Moderation) -> io::Result<()> {
+		(**self).moderate_post(p_post_id, p_moderation)
+	}
+
+
+	fn repair(&self) -> io::Result<RepairReport> {
+		(**self).repair()
+	}
+
+
+	fn compact(&self) -> io::Result<usize> {
+		(**self).compact()
+	}
+
+
+	fn save_state(&self, p_state: &BoardState) -> io::Result<()> {
+		(**self).save_state(p_state)
+	}
+
+
+	fn load_state(&self) -> io::Result<Option<BoardState>> {
+		(**self).load_state()
+	}
+
+
+	fn load_history(&self, p_history: &mut History) -> io::Result<usize> {
+		(**self).load_history(p_history)
+	}
+
+
+	fn posts_between(&self, p_first_id: u64, p_last_id: u64) -> io::Result<Vec<Post>> {
+		(**self).posts_between(p_first_id, p_last_id)
+	}
+
+
+	fn posts_of_day(&self, p_day: &str) -> io::Result<Vec<Post>> {
+		(**self).posts_of_day(p_day)
+	}
+
+
+	fn posts_with_ids(&self, p_ids: &[u64]) -> io::Result<Vec<Post>> {
+		(**self).posts_with_ids(p_ids)
+	}
+
+
+	fn for_each_post(&self, p_action: &mut dyn FnMut(Post)) -> io::Result<()> {
+		(**self).for_each_post(p_action)
+	}
+
+
+	fn prune_before(&self, p_day: &str) -> io::Result<usize> {
+		(**self).prune_before(p_day)
+	}
+
+
+	fn location(&self) -> String {
+		(**self).location()
+	}
+}
+
+
+/// Loads a stored history, then restores the sequence of its post ids from
+/// the stored state of the board.
+///
+/// The state is checked against the loaded posts: a sequence behind the last
+/// loaded post is fixed with a warning. The checked state, with the current
+/// name of the board, is then saved back. Returns the number of posts read
+/// from the storage.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::core::{ History, Post, UserPost };
+/// use oxyboard::storage::{ restore_history, BoardState, CsvFileStorage, StorageBackend };
+///
+/// let dir = std::env::temp_dir().join(format!("oxyboard-state-{}", std::process::id()));
+/// let storage = CsvFileStorage::new(&dir.to_string_lossy().to_string(), String::from("history.csv"));
+/// let request = UserPost::new(String::new(), String::from("Firefox/48.0.1"), String::from("Plop!"));
+/// storage.save_post(&Post::new(1, String::from("20161026120000"), request)).unwrap();
+///
+/// // The posts #2 to #41 were given, then lost from the history file
+/// storage.save_state(&BoardState { next_post_id: 42, board_name: String::from("Oxyboard") }).unwrap();
+///
+/// let mut history = History::new(&String::from("Plop"), 512);
+/// assert_eq!(restore_history(&storage, &mut history).unwrap(), 1);
+/// assert_eq!(history.next_post_id(), 42);
+/// assert_eq!(storage.load_state().unwrap().unwrap().board_name, "Plop");
+///
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn restore_history<S: StorageBackend + ?Sized>(p_storage: &S, p_history: &mut History) -> io::Result<usize> {
+	let count = p_storage.load_history(p_history)?;
+
+	match p_storage.load_state() {
+		Ok(Some(state)) if state.next_post_id < p_history.next_post_id() =>
+			warn_msg!("The post id sequence of '{}' was behind its posts: restored to #{}.", p_storage.location(), p_history.next_post_id()),
+		Ok(Some(state)) => p_history.skip_post_ids(state.next_post_id.saturating_sub(1)),
+		Ok(None)        => {},
+		Err(e)          => warn_msg!("Failed to read the state of '{}', the post id sequence is restored from the posts: {}", p_storage.location(), e)
+	}
+
+	p_storage.save_state(&BoardState {
+		next_post_id : p_history.next_post_id(),
+		board_name   : p_history.board_name().clone(),
+	})?;
+	Ok(count)
 }
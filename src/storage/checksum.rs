@@ -0,0 +1,31 @@
+//! The checksums of the stored records.
+//!
+//! The records of the file backends end with the CRC-32 of their content,
+//! so that a record damaged by a crash or a disk failure can be detected.
+
+
+/// The reversed polynomial of the CRC-32 used by zlib, PNG or Ethernet.
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+
+/// Computes the CRC-32 of some data.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::storage::checksum::crc32;
+///
+/// assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+/// assert_eq!(crc32(b""), 0);
+/// ```
+pub fn crc32(p_data: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for byte in p_data {
+		crc ^= *byte as u32;
+		for _ in 0..8 {
+			let mask = (crc & 1).wrapping_neg();
+			crc = (crc >> 1) ^ (POLYNOMIAL & mask);
+		}
+	}
+	!crc
+}
@@ -0,0 +1,963 @@
+//! The storage backends keeping the posts in files of records, one record
+//! per line, like the CSV and JSON Lines backends.
+
+use crate::config::RotationPolicy;
+use crate::core::{History, Post};
+use crate::core::history::Moderation;
+use crate::storage::{BoardState, RepairReport, StorageBackend};
+use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::ops::{Range, RangeInclusive};
+use std::path::Path;
+use std::sync::Mutex;
+
+
+/// A record read from a file: its byte range in the file, and the record or
+/// the reason why it cannot be read.
+pub type ReadRecord<T> = (Range<u64>, Result<T, String>);
+
+
+/// The range of the ids of a segment, if it has posts, with the length of the
+/// segment when it was read.
+type SegmentIds = (u64, Option<RangeInclusive<u64>>);
+
+
+/// The extension added to the name of the compressed segments.
+const COMPRESSED_EXTENSION: &str = ".gz";
+
+
+/// The format of the records of a `FileStorage`.
+///
+/// Each record is written on its own line, ending with a checksum of its
+/// content, so that the records damaged by a crash are detected.
+pub trait RecordFormat {
+	/// The extension of the files, without its dot.
+	const EXTENSION: &'static str;
+
+
+	/// Encodes a record as a line, ending with its checksum and a line break.
+	fn encode_record<S: Serialize>(p_record: &S) -> io::Result<Vec<u8>>;
+
+
+	/// Reads the records of a file, in order, one at a time.
+	///
+	/// Each record is given with its byte range in the file, or with the
+	/// reason why it cannot be read: its checksum does not match its content
+	/// or it is not a valid `T`. Only the failures to read the file are errors.
+	fn read_records<T: DeserializeOwned, R: BufRead>(p_input: R) -> impl Iterator<Item = io::Result<ReadRecord<T>>>;
+}
+
+
+/// A moderation, as recorded in the moderation file.
+#[derive(Serialize, Deserialize)]
+pub struct ModerationRecord {
+	/// The id of the moderated post
+	pub id         : u64,
+	/// What was done to it
+	pub moderation : Moderation,
+}
+
+
+/// `StorageBackend` implementation keeping the posts in files of records.
+///
+/// The posts are appended to the history file. As it is only appended to,
+/// the moderations are recorded in a second file, named `moderation` with
+/// the extension of the format. They are applied whenever the posts are read. The state of the board, like the id of its next post,
+/// is kept in a third file, `state.toml`, replaced whenever it changes.
+///
+/// The history can be split into segments (see `with_rotation()`), of which
+/// only the last ones are read to fill the `History`. The closed segments
+/// can be compressed (see `with_compression()`). The records damaged
+/// by a crash are skipped when the files are read, and `repair()` moves them
+/// to a `.corrupt` file, along with the incomplete record ending a file.
+///
+/// The format of the records is given by `F`: see `CsvFileStorage` and
+/// `JsonLinesStorage`.
+pub struct FileStorage<F: RecordFormat> {
+	/// Directory where the data files are stored.
+	dir: String,
+	/// Name of the history data file.
+	file: String,
+	/// How the history file is split into segments.
+	rotation: RotationPolicy,
+	/// The size in bytes of the segments, with the size rotation.
+	segment_size: u64,
+	/// Whether the closed segments are compressed.
+	compress: bool,
+	/// The segment written last, to detect when a segment is closed.
+	last_segment: Mutex<Option<String>>,
+	/// The range of the ids of the segments, with their length when read.
+	segment_ids: Mutex<HashMap<String, SegmentIds>>,
+	/// The files written since they were last flushed to the disk.
+	unsynced: Mutex<HashSet<String>>,
+	/// The format of the records.
+	format: PhantomData<F>,
+}
+
+impl<F: RecordFormat> FileStorage<F> {
+	/// Builds a new file storage backend.
+	///
+	/// It needs two informations, the name of the history file and its location (directory).
+	/// The history is not split into segments.
+	pub fn new(p_dir: &str, p_file: String) -> FileStorage<F> {
+		FileStorage {
+			dir : p_dir.to_string(),
+			file : p_file,
+			rotation : RotationPolicy::Never,
+			segment_size : u64::MAX,
+			compress     : false,
+			last_segment : Mutex::new(None),
+			segment_ids  : Mutex::new(HashMap::new()),
+			unsynced : Mutex::new(HashSet::new()),
+			format : PhantomData,
+		}
+	}
+
+
+	/// Splits the history into segments, following a rotation policy.
+	///
+	/// With the `Monthly` policy, the posts of a month are written to their
+	/// own segment, named after the month, like `history-2016-10.csv`. With the
+	/// `Size` policy, the posts are written to the history file until it
+	/// reaches `p_segment_size` bytes; it is then renamed after its number,
+	/// like `history-000001.csv`, and a new history file is started.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::config::RotationPolicy;
+	/// use oxyboard::core::{ History, Post, UserPost };
+	/// use oxyboard::storage::{ CsvFileStorage, StorageBackend };
+	///
+	/// let dir = std::env::temp_dir().join(format!("oxyboard-rotation-{}", std::process::id()));
+	/// let storage = CsvFileStorage::new(&dir.to_string_lossy().to_string(), String::from("history.csv"))
+	///         .with_rotation(RotationPolicy::Monthly, 0);
+	/// for (id, time) in &[(1, "20161026120000"), (2, "20161101120000"), (3, "20161102120000")] {
+	///     let request = UserPost::new(String::new(), String::from("Firefox/48.0.1"), String::from("Plop!"));
+	///     storage.save_post(&Post::new(*id, time.to_string(), request)).unwrap();
+	/// }
+	///
+	/// let segments = storage.segments().unwrap();
+	/// assert_eq!(segments.len(), 2);
+	/// assert!(segments[0].ends_with("history-2016-10.csv"));
+	/// assert!(segments[1].ends_with("history-2016-11.csv"));
+	///
+	/// // Only the segments needed to fill the history are loaded
+	/// let mut history = History::new(&String::from("Oxyboard"), 2);
+	/// assert_eq!(storage.load_history(&mut history).unwrap(), 2);
+	/// assert_eq!(storage.posts_of_day("20161026").unwrap().len(), 1);
+	/// assert_eq!(storage.posts_with_ids(&[1, 3]).unwrap().len(), 2);
+	///
+	/// std::fs::remove_dir_all(&dir).unwrap();
+	/// ```
+	pub fn with_rotation(mut self, p_rotation: RotationPolicy, p_segment_size: u64) -> FileStorage<F> {
+		self.rotation = p_rotation;
+		self.segment_size = p_segment_size;
+		self
+	}
+
+
+	/// Compresses the segments once they are closed, with gzip.
+	///
+	/// A segment is closed when the posts are written to another one: with
+	/// the `Monthly` policy, when a new month starts, and with the `Size`
+	/// policy, when the history file is renamed. The closed segment is
+	/// replaced by a compressed file, like `history-2016-10.csv.gz`, which
+	/// is read like the other segments. A post written later to the month of
+	/// a compressed segment goes to a new segment, compressed along with it
+	/// once closed. The history file is never compressed.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use oxyboard::config::RotationPolicy;
+	/// use oxyboard::core::{ Post, UserPost };
+	/// use oxyboard::storage::{ CsvFileStorage, StorageBackend };
+	///
+	/// let dir = std::env::temp_dir().join(format!("oxyboard-compression-{}", std::process::id()));
+	/// let storage = CsvFileStorage::new(&dir.to_string_lossy().to_string(), String::from("history.csv"))
+	///         .with_rotation(RotationPolicy::Monthly, 0)
+	///         .with_compression(true);
+	/// let post = |id: u64, time: &str| Post::new(id, time.to_string(), UserPost::new(String::new(), String::from("Firefox/48.0.1"), String::from("Plop!")));
+	/// storage.save_posts(&[ post(1, "20161026120000"), post(2, "20161101120000") ]).unwrap();
+	///
+	/// let segments = storage.segments().unwrap();
+	/// assert!(segments[0].ends_with("history-2016-10.csv.gz"));
+	/// assert!(segments[1].ends_with("history-2016-11.csv"));
+	/// assert_eq!(storage.posts_of_day("20161026").unwrap().len(), 1);
+	///
+	/// // A late post of October is compressed along with its segment
+	/// storage.save_post(&post(3, "20161031235959")).unwrap();
+	/// storage.save_post(&post(4, "20161102120000")).unwrap();
+	/// assert!(storage.segments().unwrap()[0].ends_with("history-2016-10.csv.gz"));
+	/// assert_eq!(storage.posts_between(1, 4).unwrap().len(), 4);
+	/// assert_eq!(storage.posts_of_day("20161031").unwrap().len(), 1);
+	///
+	/// std::fs::remove_dir_all(&dir).unwrap();
+	/// ```
+	pub fn with_compression(mut self, p_compress: bool) -> FileStorage<F> {
+		self.compress = p_compress;
+		self
+	}
+
+
+	/// Gives the full path of the backend file.
+	pub fn file_path(&self) -> String {
+		format!("{dir}/{file}",
+				dir = self.dir,
+				file = self.file)
+	}
+
+
+	/// Gives the full paths of the segments of the history, the oldest first.
+	///
+	/// The segments are the history file and the files named after it with
+	/// a suffix, like `history-2016-10.csv` or `history-000001.csv`, which
+	/// may be compressed, like `history-2016-10.csv.gz`. They are sorted by
+	/// the id of their first post.
+	pub fn segments(&self) -> io::Result<Vec<String>> {
+		let entries = match fs::read_dir(&self.dir) {
+			Ok(entries)                               => entries,
+			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(e)                                    => return Err(e)
+		};
+
+		let prefix = format!("{}-", self.stem());
+		let mut segments = Vec::new();
+		for entry in entries {
+			let name = entry?.file_name().to_string_lossy().to_string();
+			if name == self.file || (name.starts_with(&prefix) && self.strip_extension(&name).is_some()) {
+				let path = format!("{}/{}", self.dir, name);
+				segments.push((self.first_post_id(&path), path));
+			}
+		}
+		segments.sort();
+		Ok(segments.into_iter().map(|(_, path)| path).collect())
+	}
+
+
+	/// Gives the id of the first valid post of a segment, if any.
+	fn first_post_id(&self, p_path: &str) -> Option<u64> {
+		let input = open_reader(p_path).ok()??;
+		F::read_records::<Post, _>(input)
+				.map_while(Result::ok)
+				.find_map(|(_, post)| post.ok())
+				.map(|post| post.id())
+	}
+
+
+	/// Gives the range of the ids of the valid posts of a segment, if any.
+	///
+	/// The segment is read whole, so the range is kept until the length of
+	/// the segment changes: the closed segments are only read once.
+	fn segment_ids(&self, p_path: &str) -> io::Result<Option<RangeInclusive<u64>>> {
+		let length = match fs::metadata(p_path) {
+			Ok(metadata)                              => metadata.len(),
+			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+			Err(e)                                    => return Err(e)
+		};
+		if let Some((read_length, ids)) = self.segment_ids.lock().unwrap().get(p_path) {
+			if *read_length == length {
+				return Ok(ids.clone());
+			}
+		}
+
+		let input = match open_reader(p_path)? {
+			Some(input) => input,
+			None        => return Ok(None)
+		};
+		let mut ids: Option<RangeInclusive<u64>> = None;
+		for record in F::read_records::<Post, _>(input) {
+			if let Ok(post) = record?.1 {
+				ids = Some(match ids {
+					Some(ids) => *ids.start().min(&post.id())..=*ids.end().max(&post.id()),
+					None      => post.id()..=post.id()
+				});
+			}
+		}
+		self.segment_ids.lock().unwrap().insert(p_path.to_string(), (length, ids.clone()));
+		Ok(ids)
+	}
+
+
+	/// Gives the name of the history file, without its extension.
+	fn stem(&self) -> &str {
+		self.file.strip_suffix(&self.extension()).unwrap_or(&self.file)
+	}
+
+
+	/// Gives the extension of the files, with its dot.
+	fn extension(&self) -> String {
+		format!(".{}", F::EXTENSION)
+	}
+
+
+	/// Gives the name of a segment without its extension, compressed or not,
+	/// if it has it.
+	fn strip_extension<'a>(&self, p_name: &'a str) -> Option<&'a str> {
+		p_name.strip_suffix(COMPRESSED_EXTENSION)
+				.unwrap_or(p_name)
+				.strip_suffix(&self.extension())
+	}
+
+
+	/// Gives the month of the posts of a segment named after it, as "YYYYmm".
+	fn segment_month(&self, p_path: &str) -> Option<String> {
+		let name = Path::new(p_path).file_name()?.to_str()?;
+		let month = self.strip_extension(name)?.strip_prefix(self.stem())?.strip_prefix('-')?;
+		match month.split_once('-') {
+			Some((year, month)) if year.len() == 4 && month.len() == 2 && (year.to_string() + month).chars().all(|c| c.is_ascii_digit())
+				=> Some(format!("{}{}", year, month)),
+			_   => None
+		}
+	}
+
+
+	/// Gives the path of the segment a new post is written to.
+	fn segment_of(&self, p_post: &Post) -> String {
+		match (self.rotation, p_post.time().get(0..4), p_post.time().get(4..6)) {
+			(RotationPolicy::Monthly, Some(year), Some(month)) => format!("{}/{}-{}-{}{}", self.dir, self.stem(), year, month, self.extension()),
+			_                                                  => self.file_path()
+		}
+	}
+
+
+	/// Closes the history file when it has reached the size of a segment,
+	/// with the size rotation.
+	///
+	/// The closed file is named after the next segment number, and compressed
+	/// if asked.
+	fn rotate(&self) -> io::Result<()> {
+		if self.rotation != RotationPolicy::Size {
+			return Ok(());
+		}
+		match fs::metadata(self.file_path()) {
+			Ok(metadata) if metadata.len() >= self.segment_size => {},
+			Ok(_)                                               => return Ok(()),
+			Err(e) if e.kind() == ErrorKind::NotFound           => return Ok(()),
+			Err(e)                                              => return Err(e)
+		}
+
+		let prefix = format!("{}/{}-", self.dir, self.stem());
+		let number = self.segments()?.iter()
+				.filter_map(|s| self.strip_extension(s)?.strip_prefix(&prefix)?.parse::<u64>().ok())
+				.max()
+				.unwrap_or(0) + 1;
+
+		self.sync_file(&self.file_path())?;
+		fs::rename(self.file_path(), format!("{}{:06}{}", prefix, number, self.extension()))?;
+		self.close_segments(&self.file_path());
+		Ok(())
+	}
+
+
+	/// Records the segment the posts were written to, closing the others
+	/// when it is not the one written last.
+	fn segment_written(&self, p_segment: &str) {
+		let mut last_segment = self.last_segment.lock().unwrap();
+		if last_segment.as_deref() != Some(p_segment) {
+			self.close_segments(p_segment);
+		}
+		*last_segment = Some(p_segment.to_string());
+	}
+
+
+	/// Compresses the closed segments, if asked, all the segments but
+	/// `p_current` being closed.
+	///
+	/// The segments are compressed after the posts are written, so a failure
+	/// is only a warning: they are compressed when segments are closed again.
+	fn close_segments(&self, p_current: &str) {
+		if !self.compress {
+			return;
+		}
+		if let Err(e) = self.compress_closed_segments(p_current) {
+			warn_msg!("Failed to compress the closed segments of '{}': {}", self.file_path(), e);
+		}
+	}
+
+
+	/// Compresses the segments which are not compressed yet, but the history
+	/// file and the one being written.
+	fn compress_closed_segments(&self, p_current: &str) -> io::Result<()> {
+		for segment in self.segments()? {
+			if segment != p_current && segment != self.file_path() && !is_compressed(&segment) {
+				self.compress_segment(&segment)?;
+			}
+		}
+		Ok(())
+	}
+
+
+	/// Replaces a segment by its compressed version, named after it with the
+	/// `.gz` extension.
+	///
+	/// When the compressed segment already exists, because posts were written
+	/// to its month after it was closed, the posts are added to it as a new
+	/// gzip member, skipping the ones it already holds in case a previous
+	/// compression was stopped before the segment was removed.
+	fn compress_segment(&self, p_path: &str) -> io::Result<()> {
+		let mut input = match open_reader(p_path)? {
+			Some(input) => input,
+			None        => return Ok(())
+		};
+		let compressed_path = format!("{}{}", p_path, COMPRESSED_EXTENSION);
+		let temp_path = format!("{}.tmp", compressed_path);
+		let mut output = BufWriter::new(File::create(&temp_path)?);
+
+		let previous = match File::open(&compressed_path) {
+			Ok(file)                                  => Some(file),
+			Err(e) if e.kind() == ErrorKind::NotFound => None,
+			Err(e)                                    => return Err(e)
+		};
+		let mut encoder = match previous {
+			None           => {
+				let mut encoder = GzEncoder::new(output, Compression::default());
+				io::copy(&mut input, &mut encoder)?;
+				encoder
+			},
+			Some(mut file) => {
+				let last_id = F::read_records::<Post, _>(BufReader::new(MultiGzDecoder::new(&file)))
+						.filter_map(|record| record.ok()?.1.ok())
+						.map(|post| post.id())
+						.max();
+				file.seek(SeekFrom::Start(0))?;
+				io::copy(&mut file, &mut output)?;
+				let mut encoder = GzEncoder::new(output, Compression::default());
+				for record in F::read_records::<Post, _>(input) {
+					match record?.1 {
+						Ok(post) if Some(post.id()) > last_id => encoder.write_all(&F::encode_record(&post)?)?,
+						_                                     => {}
+					}
+				}
+				encoder
+			}
+		};
+		encoder.flush()?;
+		encoder.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+		fs::rename(&temp_path, &compressed_path)?;
+		fs::remove_file(p_path)
+	}
+
+
+	/// Gives the full path of the moderation file.
+	pub fn moderation_path(&self) -> String {
+		format!("{dir}/moderation.{ext}",
+				dir = self.dir,
+				ext = F::EXTENSION)
+	}
+
+
+	/// Gives the full path of the state file.
+	pub fn state_path(&self) -> String {
+		format!("{dir}/state.toml",
+				dir = self.dir)
+	}
+
+
+	/// Appends a record to a file of the data directory.
+	fn append<S: serde::Serialize>(&self, p_path: &str, p_record: S) -> io::Result<()> {
+		self.append_all(p_path, std::iter::once(p_record))
+	}
+
+
+	/// Appends records to a file of the data directory.
+	///
+	/// The records are written at once, each one ending with its checksum.
+	/// The file is opened in append mode and closed at the end of the function.
+	/// It is created if needed, as is its directory path.
+	fn append_all<S, I>(&self, p_path: &str, p_records: I) -> io::Result<()>
+			where S: serde::Serialize, I: IntoIterator<Item = S> {
+		let mut lines = Vec::new();
+		for record in p_records {
+			lines.extend(F::encode_record(&record).map_err(|e| io::Error::new(e.kind(), format!("Failed to encode line in file '{}': {}", p_path, e)))?);
+		}
+
+		fs::create_dir_all(&self.dir)?;
+		OpenOptions::new()
+				.create(true)
+				.append(true)
+				.open(p_path)?
+				.write_all(&lines)?;
+		self.unsynced.lock().unwrap().insert(p_path.to_string());
+		Ok(())
+	}
+
+
+	/// Flushes a file of the data directory to the disk, if it exists.
+	fn sync_file(&self, p_path: &str) -> io::Result<()> {
+		match File::open(p_path) {
+			Ok(file)                                  => file.sync_data(),
+			Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+			Err(e)                                    => Err(e)
+		}
+	}
+
+
+	/// Reads the moderations recorded in the moderation file.
+	///
+	/// When a post has been moderated several times, the last moderation wins.
+	fn read_moderations(&self) -> io::Result<HashMap<u64, Moderation>> {
+		let mut moderations = HashMap::new();
+		let input = match open_reader(&self.moderation_path())? {
+			Some(input) => input,
+			None        => return Ok(moderations)
+		};
+		for record in F::read_records(input) {
+			match record?.1 {
+				Ok(ModerationRecord { id, moderation }) => { moderations.insert(id, moderation); },
+				Err(err)                                => warn_msg!("Failed to parse moderation file '{}': {}", self.moderation_path(), &err)
+			}
+		}
+		Ok(moderations)
+	}
+
+
+	/// Reads the posts of segments, in order, passing them to `p_action`.
+	///
+	/// The moderations are applied: the deleted posts are skipped and the
+	/// masked ones are given with their masked message.
+	///
+	/// The invalid lines are skipped with a warning. A missing file has no posts.
+	/// Returns the number of posts read.
+	fn read_posts<A: FnMut(Post)>(&self, p_segments: &[String], mut p_action: A) -> io::Result<usize> {
+		let moderations = self.read_moderations()?;
+		let mut count = 0;
+		for segment in p_segments {
+			count += self.read_segment(segment, &moderations, &mut p_action)?;
+		}
+		Ok(count)
+	}
+
+
+	/// Reads the posts of a segment, in order, passing them to `p_action`.
+	///
+	/// The segment is read one record at a time. The given moderations are
+	/// applied. Returns the number of posts read.
+	fn read_segment<A: FnMut(Post)>(&self, p_path: &str, p_moderations: &HashMap<u64, Moderation>, p_action: &mut A) -> io::Result<usize> {
+		let input = match open_reader(p_path)? {
+			Some(input) => input,
+			None        => return Ok(0)
+		};
+		let mut count = 0;
+		for record in F::read_records(input) {
+			match record?.1 {
+				Ok(post) => {
+					let post: Post = post;
+					match p_moderations.get(&post.id()) {
+						Some(Moderation::Delete) => continue,
+						Some(Moderation::Mask)   => p_action(post.masked()),
+						None                     => p_action(post)
+					}
+					count += 1;
+				},
+				Err(err) => warn_msg!("Failed to parse history file '{}': {}", p_path, &err)
+			}
+		}
+		Ok(count)
+	}
+
+
+	/// Rewrites a segment without its deleted posts, and with the masked
+	/// messages of the masked ones.
+	///
+	/// The segment is read a first time to count the posts to remove or to
+	/// mask, then, if anything changes, a second time to write the kept posts
+	/// to the replacing file, compressed like the segment. An emptied segment
+	/// is removed, unless it is the history file. Returns the number of
+	/// removed posts.
+	fn compact_segment(&self, p_path: &str, p_moderations: &HashMap<u64, Moderation>) -> io::Result<usize> {
+		let (mut removed, mut kept, mut masked) = (0, 0, false);
+		self.read_segment(p_path, &HashMap::new(), &mut |post| match p_moderations.get(&post.id()) {
+			Some(Moderation::Delete) => removed += 1,
+			Some(Moderation::Mask)   => { masked = true; kept += 1; },
+			None                     => kept += 1
+		})?;
+
+		if removed == 0 && !masked {
+			return Ok(0);
+		}
+		if kept == 0 && p_path != self.file_path() {
+			fs::remove_file(p_path)?;
+			return Ok(removed);
+		}
+		let input = match open_reader(p_path)? {
+			Some(input) => input,
+			None        => return Ok(0)
+		};
+		replace_file_with(p_path, |output| {
+			for record in F::read_records::<Post, _>(input) {
+				let post = match record?.1 {
+					Ok(post) => post,
+					Err(_)   => continue
+				};
+				match p_moderations.get(&post.id()) {
+					Some(Moderation::Delete) => {},
+					Some(Moderation::Mask)   => output.write_all(&F::encode_record(&post.masked())?)?,
+					None                     => output.write_all(&F::encode_record(&post)?)?
+				}
+			}
+			Ok(())
+		})?;
+		Ok(removed)
+	}
+
+
+	/// Repairs a file of the data directory after a crash.
+	///
+	/// The incomplete record ending the file, if any, is cut, and the records
+	/// which cannot be read as a `T` are removed from the file. Both are
+	/// appended to a `.corrupt` file next to it.
+	///
+	/// The file is read one record at a time, only the byte ranges of the
+	/// damaged records being kept; the valid ones are then copied to the
+	/// repaired file.
+	fn repair_file<T: DeserializeOwned>(&self, p_path: &str) -> io::Result<RepairReport> {
+		let mut file = match File::open(p_path) {
+			Ok(file)                                  => file,
+			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(RepairReport::default()),
+			Err(e)                                    => return Err(e)
+		};
+
+		// A complete record ends with a line break
+		let length = file.metadata()?.len();
+		let complete = complete_length(&mut file)?;
+		file.seek(SeekFrom::Start(0))?;
+		let mut corrupted: Vec<Range<u64>> = Vec::new();
+		for record in F::read_records::<T, _>(BufReader::new((&mut file).take(complete))) {
+			let (range, record) = record?;
+			if record.is_err() {
+				corrupted.push(range);
+			}
+		}
+		let torn = complete..length;
+		if torn.is_empty() && corrupted.is_empty() {
+			return Ok(RepairReport::default());
+		}
+
+		// Keep the removed data aside
+		let quarantine_path = format!("{}.corrupt", p_path);
+		let mut quarantine = OpenOptions::new()
+				.create(true)
+				.append(true)
+				.open(&quarantine_path)?;
+		for range in &corrupted {
+			copy_range(&mut file, range, &mut quarantine)?;
+		}
+		if !torn.is_empty() {
+			copy_range(&mut file, &torn, &mut quarantine)?;
+			quarantine.write_all(b"\n")?;
+		}
+		quarantine.sync_all()?;
+
+		// Replace the file by its valid records
+		replace_file_with(p_path, |output| {
+			let mut start = 0;
+			for range in &corrupted {
+				copy_range(&mut file, &(start..range.start), output)?;
+				start = range.end;
+			}
+			copy_range(&mut file, &(start..complete), output)
+		})?;
+
+		Ok(RepairReport {
+			truncated_bytes  : (length - complete) as usize,
+			quarantined      : corrupted.len(),
+			quarantine_files : vec![ quarantine_path ],
+		})
+	}
+}
+
+
+/// Replaces the content of a file, through a temporary file so that it is
+/// never partially written.
+fn replace_file(p_path: &str, p_data: &[u8]) -> io::Result<()> {
+	replace_file_with(p_path, |output| output.write_all(p_data))
+}
+
+
+/// Replaces the content of a file by what `p_write` writes, through a
+/// temporary file so that it is never partially written.
+///
+/// A compressed file is written compressed.
+fn replace_file_with<W>(p_path: &str, p_write: W) -> io::Result<()>
+		where W: FnOnce(&mut dyn Write) -> io::Result<()> {
+	let temp_path = format!("{}.tmp", p_path);
+	let mut output = BufWriter::new(File::create(&temp_path)?);
+	if is_compressed(p_path) {
+		let mut encoder = GzEncoder::new(output, Compression::default());
+		p_write(&mut encoder)?;
+		output = encoder.finish()?;
+	} else {
+		p_write(&mut output)?;
+	}
+	output.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+	fs::rename(&temp_path, p_path)
+}
+
+
+/// Tells whether a file is compressed, after its extension.
+fn is_compressed(p_path: &str) -> bool {
+	p_path.ends_with(COMPRESSED_EXTENSION)
+}
+
+
+/// Opens a file for reading, a missing file being `None`.
+///
+/// A compressed file is read uncompressed.
+fn open_reader(p_path: &str) -> io::Result<Option<Box<dyn BufRead>>> {
+	match File::open(p_path) {
+		Ok(file) if is_compressed(p_path)         => Ok(Some(Box::new(BufReader::new(MultiGzDecoder::new(file))))),
+		Ok(file)                                  => Ok(Some(Box::new(BufReader::new(file)))),
+		Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+		Err(e)                                    => Err(e)
+	}
+}
+
+
+/// Gives the length of the complete records of a file, up to its last line
+/// break, reading it backwards from its end.
+fn complete_length(p_file: &mut File) -> io::Result<u64> {
+	let mut chunk = [0u8; 4096];
+	let mut end = p_file.seek(SeekFrom::End(0))?;
+	while end > 0 {
+		let start = end.saturating_sub(chunk.len() as u64);
+		let part = &mut chunk[..(end - start) as usize];
+		p_file.seek(SeekFrom::Start(start))?;
+		p_file.read_exact(part)?;
+		if let Some(i) = part.iter().rposition(|b| *b == b'\n') {
+			return Ok(start + i as u64 + 1);
+		}
+		end = start;
+	}
+	Ok(0)
+}
+
+
+/// Copies a byte range of a file to an output.
+fn copy_range<W: Write + ?Sized>(p_file: &mut File, p_range: &Range<u64>, p_output: &mut W) -> io::Result<()> {
+	p_file.seek(SeekFrom::Start(p_range.start))?;
+	io::copy(&mut (&mut *p_file).take(p_range.end - p_range.start), p_output)?;
+	Ok(())
+}
+
+
+impl<F: RecordFormat> StorageBackend for FileStorage<F> {
+	/// Appends the representation of a post to its segment.
+	///
+	/// The output file is opened in append mode and closed at the end of the function.
+	/// It is created if needed, as is its directory path.
+	fn save_post(&self, p_post: &Post) -> io::Result<()> {
+		self.save_posts(std::slice::from_ref(p_post))
+	}
+
+
+	/// Appends the representations of the posts to their segments, opening
+	/// each one once.
+	fn save_posts(&self, p_posts: &[Post]) -> io::Result<()> {
+		self.rotate()?;
+		let mut start = 0;
+		while start < p_posts.len() {
+			let segment = self.segment_of(&p_posts[start]);
+			let length = p_posts[start..].iter().take_while(|p| self.segment_of(p) == segment).count();
+			self.append_all(&segment, &p_posts[start..start + length])?;
+			self.segment_written(&segment);
+			start += length;
+		}
+		Ok(())
+	}
+
+
+	/// Flushes the files written since the last flush to the disk.
+	fn sync(&self) -> io::Result<()> {
+		let unsynced: Vec<String> = self.unsynced.lock().unwrap().drain().collect();
+		for path in unsynced {
+			self.sync_file(&path)?;
+		}
+		Ok(())
+	}
+
+
+	/// Appends the moderation to the moderation file.
+	fn moderate_post(&self, p_post_id: u64, p_moderation: Moderation) -> io::Result<()> {
+		self.append(&self.moderation_path(), ModerationRecord { id: p_post_id, moderation: p_moderation })
+	}
+
+
+	/// Replaces the state file, through a temporary file.
+	///
+	/// The file is flushed to the disk by the next `sync()`.
+	fn save_state(&self, p_state: &BoardState) -> io::Result<()> {
+		let data = toml::to_string(p_state).map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+		fs::create_dir_all(&self.dir)?;
+		let temp_path = format!("{}.tmp", self.state_path());
+		fs::write(&temp_path, data)?;
+		fs::rename(&temp_path, self.state_path())?;
+		self.unsynced.lock().unwrap().insert(self.state_path());
+		Ok(())
+	}
+
+
+	/// Reads the state file, a missing file being no state.
+	fn load_state(&self) -> io::Result<Option<BoardState>> {
+		match fs::read_to_string(self.state_path()) {
+			Ok(data)                                  => toml::from_str(&data)
+					.map(Some)
+					.map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string())),
+			Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+			Err(e)                                    => Err(e)
+		}
+	}
+
+
+	/// Repairs the segments and the moderation file.
+	///
+	/// The compressed segments are skipped: they were complete when compressed.
+	fn repair(&self) -> io::Result<RepairReport> {
+		let mut report = RepairReport::default();
+		for segment in self.segments()?.iter().filter(|s| !is_compressed(s)) {
+			report.merge(self.repair_file::<Post>(segment)?);
+		}
+		report.merge(self.repair_file::<ModerationRecord>(&self.moderation_path())?);
+		Ok(report)
+	}
+
+
+	/// Rewrites the segments without the deleted posts, and with the masked
+	/// messages of the masked ones.
+	///
+	/// The segments are repaired first, so that no damaged record is lost.
+	/// Only the moderation of the last deleted post is kept, so that its id
+	/// is not given again.
+	fn compact(&self) -> io::Result<usize> {
+		self.repair()?;
+		let moderations = self.read_moderations()?;
+		let mut removed = 0;
+		for segment in self.segments()? {
+			removed += self.compact_segment(&segment, &moderations)?;
+		}
+
+		let last_deleted = moderations.iter()
+				.filter(|(_, m)| **m == Moderation::Delete)
+				.map(|(id, _)| *id)
+				.max();
+		let mut lines = Vec::new();
+		if let Some(id) = last_deleted {
+			lines.extend(F::encode_record(&ModerationRecord { id, moderation: Moderation::Delete })?);
+		}
+		if !moderations.is_empty() {
+			replace_file(&self.moderation_path(), &lines)?;
+		}
+		Ok(removed)
+	}
+
+
+	/// Loads the last segments of the history into an `History`.
+	///
+	/// The segments are read from the most recent one, until there are
+	/// enough posts to fill the history.
+	///
+	/// Returns the number of posts loaded from the segments. Note that this is the number
+	/// of posts read from the files. The actual number of posts in the history may be
+	/// lower if it has reached its maximum size.
+	///
+	/// A missing file is an empty history.
+	fn load_history(&self, p_history: &mut History) -> io::Result<usize> {
+		let moderations = self.read_moderations()?;
+		let mut segments = Vec::new();
+		let mut count = 0;
+		for segment in self.segments()?.iter().rev() {
+			if count >= p_history.max_size() {
+				break;
+			}
+			let mut posts = Vec::new();
+			count += self.read_segment(segment, &moderations, &mut |post| posts.push(post))?;
+			segments.push(posts);
+		}
+		for post in segments.into_iter().rev().flatten() {
+			p_history.add_full_post(post);
+		}
+
+		// The ids of the deleted posts must not be given again
+		let last_deleted = moderations.into_iter()
+				.filter(|(_, m)| *m == Moderation::Delete)
+				.map(|(id, _)| id)
+				.max();
+		if let Some(id) = last_deleted {
+			p_history.skip_post_ids(id);
+		}
+		Ok(count)
+	}
+
+
+	/// Reads all the segments, keeping the posts of the id range.
+	fn posts_between(&self, p_first_id: u64, p_last_id: u64) -> io::Result<Vec<Post>> {
+		let mut posts = Vec::new();
+		self.read_posts(&self.segments()?, |post| if post.id() >= p_first_id && post.id() <= p_last_id {
+			posts.push(post);
+		})?;
+		Ok(posts)
+	}
+
+
+	/// Reads the segments whose range of ids holds some of the ids, keeping
+	/// the posts with the ids.
+	fn posts_with_ids(&self, p_ids: &[u64]) -> io::Result<Vec<Post>> {
+		let ids: HashSet<u64> = p_ids.iter().copied().collect();
+		let mut segments = Vec::new();
+		for segment in self.segments()? {
+			if let Some(range) = self.segment_ids(&segment)? {
+				if ids.iter().any(|id| range.contains(id)) {
+					segments.push(segment);
+				}
+			}
+		}
+
+		let mut posts = Vec::new();
+		self.read_posts(&segments, |post| if ids.contains(&post.id()) {
+			posts.push(post);
+		})?;
+		Ok(posts)
+	}
+
+
+	/// Reads all the segments, one at a time.
+	fn for_each_post(&self, p_action: &mut dyn FnMut(Post)) -> io::Result<()> {
+		self.read_posts(&self.segments()?, p_action).map(|_| ())
+	}
+
+
+	/// Reads the segments which may hold the posts of the day, keeping them.
+	///
+	/// The segments named after another month are skipped.
+	fn posts_of_day(&self, p_day: &str) -> io::Result<Vec<Post>> {
+		let segments: Vec<String> = self.segments()?.into_iter()
+				.filter(|s| match self.segment_month(s) {
+					Some(month) => p_day.starts_with(&month),
+					None        => true
+				})
+				.collect();
+
+		let mut posts = Vec::new();
+		self.read_posts(&segments, |post| if post.time().starts_with(p_day) {
+			posts.push(post);
+		})?;
+		Ok(posts)
+	}
+
+
+	/// Gives the path of the history file.
+	fn location(&self) -> String {
+		self.file_path()
+	}
+}
+
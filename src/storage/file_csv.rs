@@ -1,21 +1,25 @@
-//!
-/// The CSV storage backend.
+//! The CSV storage backend.
 
 use csv;
-use crate::core::{History, Post};
-use crate::storage::StorageBackend;
-use std::fs;
-use std::fs::OpenOptions;
+use crate::storage::checksum::crc32;
+use crate::storage::file::{FileStorage, ReadRecord, RecordFormat};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use std::io;
-use std::io::ErrorKind;
+use std::io::{BufRead, ErrorKind};
 
 
-/// `StorageBackend` implementation using a CSV file.
+/// The prefix of the checksum field ending the records.
+const CHECKSUM_PREFIX: &str = "crc32=";
+
+
+/// `StorageBackend` implementation using CSV files.
 ///
 /// Each post is stored in one line.
-/// Fields are separated by `;` and are enclosed in double-quotes (`"`).
+/// Fields are separated by `,` and are only enclosed in double-quotes (`"`)
+/// when they contain a separator, a quote or a line break.
 /// The `Post` fields are stored in the following order:
-/// `id;time;user_agent;login;message`
+/// `id,time,login,user_agent,message,instant,index`
 ///
 /// For example, the following post
 ///
@@ -26,81 +30,194 @@ use std::io::ErrorKind;
 /// let post = Post::new(42, String::from("20161026120000"), request);
 /// ```
 ///
-/// is stored as `"42","20161026120000","Firefox/48.0.1","","Plop!"`.
-pub struct CsvFileStorage {
-	/// Directory where the data files are stored.
-	dir: String,
-	/// Name of the history data file.
-	file: String,
-}
+/// is stored as `42,20161026120000,,Firefox/48.0.1,Plop!,2016-10-26T12:00:00Z,1,crc32=e2eb2073`
+/// on a server in the UTC timezone. The lines stored before the `instant`
+/// and `index` fields existed are still read, their time being in the local
+/// timezone and their index being 1.
+///
+/// As the history file is only appended to, the moderations are recorded in
+/// a second CSV file, `moderation.csv`, as `id,moderation` lines (for example
+/// `42,delete,crc32=d1f15fbc`). They are applied whenever the posts are read.
+///
+/// Each line of both files ends with a `crc32=<checksum>` field, the CRC-32
+/// of the rest of the line in hexadecimal, so that the lines damaged by a
+/// crash are detected. They are skipped when the files are read, and
+/// `repair()` moves them to a `.corrupt` file, along with the incomplete
+/// line ending a file. The lines stored before the checksums existed are
+/// still read.
+///
+/// The state of the board, like the id of its next post, is kept in a third
+/// file, `state.toml`, replaced whenever it changes.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::core::{ Post, UserPost };
+/// use oxyboard::storage::{ CsvFileStorage, StorageBackend };
+/// use std::fs;
+/// use std::io::Write;
+///
+/// let dir = std::env::temp_dir().join(format!("oxyboard-csv-{}", std::process::id()));
+/// let storage = CsvFileStorage::new(&dir.to_string_lossy().to_string(), String::from("history.csv"));
+/// for (id, message) in &[(1, "Plop!"), (2, "Pika,\n\"pika\"!")] {
+///     let request = UserPost::new(String::new(), String::from("Firefox/48.0.1"), message.to_string());
+///     storage.save_post(&Post::new(*id, String::from("20161026120000"), request)).unwrap();
+/// }
+///
+/// // A quoted line break does not end a record
+/// assert_eq!(storage.posts_between(1, 2).unwrap()[1].message(), "Pika,\n\"pika\"!");
+///
+/// // Damage the first post and leave an incomplete one, as a crash would
+/// let data = fs::read_to_string(storage.file_path()).unwrap().replace("Plop!", "Plip!");
+/// fs::write(storage.file_path(), data + "3,2016102612").unwrap();
+///
+/// let report = storage.repair().unwrap();
+/// assert_eq!(report.quarantined,     1);
+/// assert_eq!(report.truncated_bytes, 12);
+/// assert_eq!(storage.posts_between(1, 3).unwrap().len(), 1);
+/// assert!(storage.repair().unwrap().is_clean());
+///
+/// fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub type CsvFileStorage = FileStorage<Csv>;
 
-impl CsvFileStorage {
-	/// Builds a new CSV file storage backend.
-	///
-	/// It needs two informations, the name of the CSV file and its location (directory).
-	pub fn new(p_dir: &String, p_file: String) -> CsvFileStorage {
-		CsvFileStorage {
-			dir : p_dir.clone(),
-			file : p_file,
-		}
-	}
 
+/// The CSV format of the records.
+pub struct Csv;
+
+impl RecordFormat for Csv {
+	const EXTENSION: &'static str = "csv";
 
-	/// Gives the full path of the backend file.
-	pub fn file_path(&self) -> String {
-		format!("{dir}/{file}",
-				dir = self.dir,
-				file = self.file)
-	}
-}
 
-impl StorageBackend for CsvFileStorage {
-	/// Appends the representation of a post to the CSV file.
-	///
-	/// The output file is opened in append mode and closed at the end of the function.
-	/// It is created if needed, as is its directory path.
-	fn save_post(&self, p_post: &Post) -> io::Result<&Self> {
-		fs::create_dir_all(&self.dir)?;
-		let mut writer = csv::Writer::from_writer(
-				OpenOptions::new()
-					.create(true)
-					.append(true)
-					.open(&self.file_path())? );
-
-		writer.serialize(p_post).and(Ok(self)).map_err(|e| {
+	/// Encodes a record as a CSV line ending with its checksum.
+	fn encode_record<S: Serialize>(p_record: &S) -> io::Result<Vec<u8>> {
+		encode_record(p_record).map_err(|e| {
 			match e.kind() {
-				&csv::ErrorKind::Serialize(ref msg) => io::Error::new(ErrorKind::Other, format!("Failed to encode line in history file '{}': {}", self.file_path(), &msg)),
+				&csv::ErrorKind::Serialize(ref msg) => io::Error::new(ErrorKind::Other, msg.clone()),
 				&csv::ErrorKind::Io(ref err)        => io::Error::new(err.kind(), err.to_string()),
-				_                                   => io::Error::new(ErrorKind::Other, "Error while saving post")
+				_                                   => io::Error::new(ErrorKind::Other, "Error while saving record")
 			}
 		})
 	}
 
 
-	/// Loads a full CSV file into an `History`.
-	///
-	/// Returns the number of posts loaded from the file. Note that this is the number
-	/// of posts read from the file. The actual number of posts in the history may be
-	/// lower if it has reached its maximum size.
-	fn load_history(&self, p_history: &mut History) -> io::Result<usize> {
-		let mut reader = csv::ReaderBuilder::new()
-				.has_headers(false)
-                .from_path(self.file_path())?;
-
-		let mut count = 0;
-		for line in reader.deserialize() {
-			match line {
-				Ok(post) => {
-					p_history.add_full_post(post);
-					count += 1;
-				},
-				Err(err) => match err.kind() {
-					&csv::ErrorKind::Serialize(ref err) => warn_msg!("Failed to parse history file '{}': {}", self.file_path(), &err),
-					&csv::ErrorKind::Io(ref err)        => warn_msg!("Failed to read history file '{}': {}",  self.file_path(), &err),
-					_ => {}
+	fn read_records<T: DeserializeOwned, R: BufRead>(p_input: R) -> impl Iterator<Item = io::Result<ReadRecord<T>>> {
+		read_records(p_input)
+	}
+}
+
+
+/// Encodes a record as a CSV line ending with its checksum.
+fn encode_record<S: Serialize>(p_record: &S) -> csv::Result<Vec<u8>> {
+	let mut writer = csv::WriterBuilder::new()
+			.has_headers(false)
+			.from_writer(Vec::new());
+	writer.serialize(p_record)?;
+	let mut line = writer.into_inner().map_err(|e| csv::Error::from(e.into_error()))?;
+
+	let length = trim_line(&line).len();
+	line.truncate(length);
+	let checksum = crc32(&line);
+	line.extend_from_slice(format!(",{}{:08x}\n", CHECKSUM_PREFIX, checksum).as_bytes());
+	Ok(line)
+}
+
+
+/// Reads the records of CSV data, in order, one at a time.
+///
+/// Each record is given with its byte range in the data, or with the reason
+/// why it cannot be read: its checksum does not match its content or it is
+/// not a valid `T`. The posts stored before their instant and index existed
+/// have fewer fields, and the records stored before the checksums existed have
+/// none. Once a record has a checksum, the next ones must have one too.
+///
+/// A record ends with the first line break out of a quoted field. The blank
+/// lines are skipped.
+fn read_records<T: DeserializeOwned, R: BufRead>(mut p_input: R) -> impl Iterator<Item = io::Result<ReadRecord<T>>> {
+	let mut checked = false;
+	let mut start = 0;
+	std::iter::from_fn(move || {
+		loop {
+			let mut data = Vec::new();
+			loop {
+				match p_input.read_until(b'\n', &mut data) {
+					Ok(0)                      => break,
+					Ok(_) if quote_open(&data) => continue,
+					Ok(_)                      => break,
+					Err(e)                     => return Some(Err(e))
 				}
 			}
+			if data.is_empty() {
+				return None;
+			}
+
+			let range = start..start + data.len() as u64;
+			start = range.end;
+			let line = trim_line(&data);
+			if line.is_empty() {
+				continue;
+			}
+
+			let decoded = match checksum_position(line) {
+				Some(i)         => { checked = true; check_record(line, i) },
+				None if checked => Err(String::from("missing checksum")),
+				None            => decode_record(line)
+			};
+			return Some(Ok((range, decoded)));
 		}
-		Ok(count)
+	})
+}
+
+
+/// Tells whether a quoted field is still open at the end of some CSV data:
+/// the quotes are doubled inside the quoted fields, so an odd number of them
+/// leaves one open.
+fn quote_open(p_data: &[u8]) -> bool {
+	p_data.iter().filter(|b| **b == b'"').count() % 2 == 1
+}
+
+
+/// Gives the position of the separator of the checksum ending a line, if any.
+fn checksum_position(p_line: &[u8]) -> Option<usize> {
+	p_line.iter().rposition(|b| *b == b',')
+			.filter(|i| p_line[i + 1..].starts_with(CHECKSUM_PREFIX.as_bytes()))
+}
+
+
+/// Checks the checksum of a record, then decodes the rest of it.
+fn check_record<T: DeserializeOwned>(p_line: &[u8], p_position: usize) -> Result<T, String> {
+	let expected = std::str::from_utf8(&p_line[p_position + 1 + CHECKSUM_PREFIX.len()..]).ok()
+			.and_then(|c| u32::from_str_radix(c, 16).ok());
+	if expected != Some(crc32(&p_line[..p_position])) {
+		return Err(String::from("invalid checksum"));
 	}
+
+	decode_record(&p_line[..p_position])
+}
+
+
+/// Decodes a record from its line.
+fn decode_record<T: DeserializeOwned>(p_line: &[u8]) -> Result<T, String> {
+	let mut reader = csv::ReaderBuilder::new()
+			.has_headers(false)
+			.flexible(true)
+			.from_reader(p_line);
+	let mut record = csv::ByteRecord::new();
+	match reader.read_byte_record(&mut record) {
+		Ok(true)  => {},
+		Ok(false) => return Err(String::from("empty record")),
+		Err(e)    => return Err(e.to_string())
+	}
+
+	csv::StringRecord::from_byte_record(record)
+			.map_err(|e| e.to_string())?
+			.deserialize(None)
+			.map_err(|e| e.to_string())
+}
+
+
+/// Removes the line break ending a line.
+fn trim_line(p_line: &[u8]) -> &[u8] {
+	let length = p_line.iter().rposition(|b| *b != b'\n' && *b != b'\r').map_or(0, |i| i + 1);
+	&p_line[..length]
 }
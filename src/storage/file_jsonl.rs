@@ -0,0 +1,159 @@
+//! The JSON Lines storage backend.
+
+use crate::storage::checksum::crc32;
+use crate::storage::file::{FileStorage, ReadRecord, RecordFormat};
+use rocket::serde::json::serde_json;
+use rocket::serde::json::serde_json::Value;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::io;
+use std::io::{BufRead, ErrorKind};
+
+
+/// The version of the schema of the records, written in each one.
+pub const SCHEMA_VERSION: u64 = 1;
+
+
+/// The beginning of the checksum field ending the records.
+const CHECKSUM_FIELD: &str = ",\"crc32\":\"";
+
+
+/// The length of the checksum field, with the end of its record.
+const CHECKSUM_LENGTH: usize = CHECKSUM_FIELD.len() + 8 + 2;
+
+
+/// `StorageBackend` implementation using JSON Lines files.
+///
+/// Each post is stored in one line, as a JSON object with the fields of the
+/// serialized `Post`, after the `version` of the schema of the record and
+/// before the `crc32` of the rest of the object. For example, the following post
+///
+/// ```
+/// use oxyboard::core::{ Post, UserPost };
+///
+/// let request = UserPost::new(String::from(""), String::from("Firefox/48.0.1"), String::from("Plop!"));
+/// let post = Post::new(42, String::from("20161026120000"), request);
+/// ```
+///
+/// is stored as `{"version":1,"id":42,"time":"20161026120000","login":"","user_agent":"Firefox/48.0.1","message":"Plop!","instant":"2016-10-26T12:00:00Z","index":1,"crc32":"f82d7033"}`
+/// on a server in the UTC timezone, so that the files can be processed by
+/// the JSON tools. The checksum is computed over the object without it.
+///
+/// The moderations are recorded in `moderation.jsonl`, like
+/// `{"version":1,"id":42,"moderation":"delete","crc32":"8d9178df"}`. The records of
+/// a newer schema version, or without a valid checksum, are skipped when the
+/// files are read, and moved to a `.corrupt` file by `repair()`.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::core::{ History, Post, UserPost };
+/// use oxyboard::storage::{ JsonLinesStorage, StorageBackend };
+/// use rocket::serde::json::serde_json::{ self, Value };
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("oxyboard-jsonl-{}", std::process::id()));
+/// let storage = JsonLinesStorage::new(&dir.to_string_lossy().to_string(), String::from("history.jsonl"));
+/// for (id, message) in &[(1, "Plop!"), (2, "Pika!")] {
+///     let request = UserPost::new(String::new(), String::from("Firefox/48.0.1"), message.to_string());
+///     storage.save_post(&Post::new(*id, String::from("20161026120000"), request)).unwrap();
+/// }
+///
+/// let data = fs::read_to_string(storage.file_path()).unwrap();
+/// let records: Vec<Value> = data.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+/// assert_eq!(records[1]["version"], 1);
+/// assert_eq!(records[1]["message"], "Pika!");
+/// assert_eq!(records[1]["index"],   1);
+///
+/// // Damage the first post and leave an incomplete one, as a crash would
+/// fs::write(storage.file_path(), data.replace("Plop!", "Plip!") + "{\"version\":1,\"id\":3").unwrap();
+///
+/// let report = storage.repair().unwrap();
+/// assert_eq!(report.quarantined, 1);
+/// let mut history = History::new(&String::from("Oxyboard"), 512);
+/// assert_eq!(storage.load_history(&mut history).unwrap(), 1);
+///
+/// fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub type JsonLinesStorage = FileStorage<JsonLines>;
+
+
+/// The JSON Lines format of the records.
+pub struct JsonLines;
+
+impl RecordFormat for JsonLines {
+	const EXTENSION: &'static str = "jsonl";
+
+
+	/// Encodes a record as a JSON object, with the version of its schema and
+	/// ending with its checksum.
+	fn encode_record<S: Serialize>(p_record: &S) -> io::Result<Vec<u8>> {
+		let mut line = serde_json::to_vec(&VersionedRecord { version: SCHEMA_VERSION, record: p_record })?;
+		if line.last() != Some(&b'}') {
+			return Err(io::Error::new(ErrorKind::InvalidInput, "a record must be a JSON object"));
+		}
+
+		let checksum = crc32(&line);
+		line.pop();
+		line.extend_from_slice(format!("{}{:08x}\"}}\n", CHECKSUM_FIELD, checksum).as_bytes());
+		Ok(line)
+	}
+
+
+	/// Reads the records of JSON Lines data, in order, one line at a time.
+	///
+	/// The blank lines are skipped.
+	fn read_records<T: DeserializeOwned, R: BufRead>(mut p_input: R) -> impl Iterator<Item = io::Result<ReadRecord<T>>> {
+		let mut start = 0;
+		std::iter::from_fn(move || {
+			loop {
+				let mut data = Vec::new();
+				match p_input.read_until(b'\n', &mut data) {
+					Ok(0)  => return None,
+					Ok(_)  => {},
+					Err(e) => return Some(Err(e))
+				}
+
+				let range = start..start + data.len() as u64;
+				start = range.end;
+				let line = data.trim_ascii();
+				if !line.is_empty() {
+					return Some(Ok((range, decode_record(line))));
+				}
+			}
+		})
+	}
+}
+
+
+/// A record, preceded by the version of its schema.
+#[derive(Serialize)]
+struct VersionedRecord<'a, S: Serialize> {
+	version : u64,
+	#[serde(flatten)]
+	record  : &'a S,
+}
+
+
+/// Checks the checksum of a line, then decodes the record it holds.
+fn decode_record<T: DeserializeOwned>(p_line: &[u8]) -> Result<T, String> {
+	let position = match p_line.len().checked_sub(CHECKSUM_LENGTH) {
+		Some(i) if p_line[i..].starts_with(CHECKSUM_FIELD.as_bytes()) && p_line.ends_with(b"\"}") => i,
+		_ => return Err(String::from("missing checksum"))
+	};
+	let expected = std::str::from_utf8(&p_line[position + CHECKSUM_FIELD.len()..p_line.len() - 2]).ok()
+			.and_then(|c| u32::from_str_radix(c, 16).ok());
+
+	let mut object = p_line[..position].to_vec();
+	object.push(b'}');
+	if expected != Some(crc32(&object)) {
+		return Err(String::from("invalid checksum"));
+	}
+
+	let record: Value = serde_json::from_slice(&object).map_err(|e| e.to_string())?;
+	match record.get("version").and_then(Value::as_u64) {
+		Some(version) if version <= SCHEMA_VERSION => serde_json::from_value(record).map_err(|e| e.to_string()),
+		Some(version)                              => Err(format!("unsupported schema version {}", version)),
+		None                                       => Err(String::from("missing schema version"))
+	}
+}
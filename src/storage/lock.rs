@@ -0,0 +1,62 @@
+//! The advisory lock of a data directory.
+
+use std::fs;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::io::ErrorKind;
+
+
+/// The name of the lock file, in the data directory.
+const LOCK_FILE: &str = "oxyboard.lock";
+
+
+/// The advisory lock of a data directory, held by the process using its files.
+///
+/// The server holds the lock of the data directory of each board while it
+/// runs, and the administration tasks hold it while they use the storage of
+/// a board, so that a task rewriting the files, like `compact`, cannot run
+/// while the server writes them. The lock is a `flock` of the `oxyboard.lock`
+/// file of the directory, released when it is dropped or when the process ends.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::storage::DataLock;
+/// use std::io::ErrorKind;
+///
+/// let dir = std::env::temp_dir().join(format!("oxyboard-lock-{}", std::process::id()));
+/// let dir = dir.to_string_lossy().to_string();
+///
+/// let lock = DataLock::acquire(&dir).unwrap();
+/// assert_eq!(DataLock::acquire(&dir).err().unwrap().kind(), ErrorKind::WouldBlock);
+///
+/// drop(lock);
+/// assert!(DataLock::acquire(&dir).is_ok());
+///
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct DataLock {
+	/// The locked file, unlocked when closed.
+	_file : File,
+}
+
+impl DataLock {
+	/// Takes the lock of a data directory, creating it if needed.
+	///
+	/// Fails with `ErrorKind::WouldBlock` if the lock is held by another process.
+	pub fn acquire(p_dir: &str) -> io::Result<DataLock> {
+		fs::create_dir_all(p_dir)?;
+		let path = format!("{}/{}", p_dir, LOCK_FILE);
+		let file = OpenOptions::new()
+				.create(true)
+				.truncate(false)
+				.write(true)
+				.open(&path)?;
+
+		match file.try_lock() {
+			Ok(())                        => Ok(DataLock { _file: file }),
+			Err(TryLockError::WouldBlock) => Err(io::Error::new(ErrorKind::WouldBlock, format!("'{}' is locked by another process", path))),
+			Err(TryLockError::Error(e))   => Err(e)
+		}
+	}
+}
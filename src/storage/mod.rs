@@ -1,9 +1,45 @@
 //!
 /// The data storage interfaces and facilities.
 
+pub use self::backend::restore_history;
+pub use self::backend::BoardState;
+pub use self::backend::RepairReport;
 pub use self::backend::StorageBackend;
+pub use self::file::FileStorage;
 pub use self::file_csv::CsvFileStorage;
+pub use self::file_jsonl::JsonLinesStorage;
+pub use self::lock::DataLock;
+pub use self::sqlite::SqliteStorage;
+
+use crate::config::{StorageKind, StorageParams};
+use std::io;
+use std::sync::Arc;
 
 // The storage backends are defined in sub-modules
+pub mod file;
 pub mod file_csv;
+pub mod file_jsonl;
 pub mod backend;
+pub mod checksum;
+pub mod lock;
+pub mod sqlite;
+
+
+/// A storage backend shared between the threads of the server.
+pub type SharedStorage = Arc<dyn StorageBackend + Send + Sync>;
+
+
+/// Opens the storage backend selected by the configuration.
+///
+/// The data of the backend are stored in the `p_dir` directory.
+pub fn open(p_params: &StorageParams, p_dir: &str) -> io::Result<Box<dyn StorageBackend + Send + Sync>> {
+	match p_params.backend {
+		StorageKind::Csv    => Ok(Box::new(CsvFileStorage::new(p_dir, String::from("history.csv"))
+				.with_rotation(p_params.rotation, p_params.segment_size.saturating_mul(1024 * 1024))
+				.with_compression(p_params.compress))),
+		StorageKind::Jsonl  => Ok(Box::new(JsonLinesStorage::new(p_dir, String::from("history.jsonl"))
+				.with_rotation(p_params.rotation, p_params.segment_size.saturating_mul(1024 * 1024))
+				.with_compression(p_params.compress))),
+		StorageKind::Sqlite => Ok(Box::new(SqliteStorage::open(p_dir, "history.sqlite")?)),
+	}
+}
@@ -0,0 +1,318 @@
+//! The SQLite storage backend.
+
+use crate::core::{History, Post, UserPost};
+use crate::core::history::Moderation;
+use crate::core::post::MASKED_MESSAGE;
+use crate::storage::{BoardState, StorageBackend};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+
+
+/// The schema of the database, created if needed when it is opened.
+const SCHEMA: &str = "
+	CREATE TABLE IF NOT EXISTS posts (
+		id         INTEGER PRIMARY KEY,
+		time       TEXT    NOT NULL,
+		login      TEXT    NOT NULL,
+		user_agent TEXT    NOT NULL,
+		message    TEXT    NOT NULL,
+		instant    TEXT,
+		second_index INTEGER NOT NULL DEFAULT 1
+	);
+	CREATE INDEX IF NOT EXISTS posts_time ON posts (time);
+	CREATE TABLE IF NOT EXISTS deleted_posts (
+		id         INTEGER PRIMARY KEY
+	);
+	CREATE TABLE IF NOT EXISTS board_state (
+		id           INTEGER PRIMARY KEY CHECK (id = 1),
+		next_post_id INTEGER NOT NULL,
+		board_name   TEXT    NOT NULL
+	);
+";
+
+
+/// `StorageBackend` implementation using an SQLite database.
+///
+/// Each post is a row of the `posts` table, whose primary key is the post id.
+/// The `time` column is indexed too, so that the posts can be queried by date.
+/// The `second_index` column holds the index of the post in its second.
+/// The `instant` column holds the UTC instant of the post as RFC 3339; it is
+/// added to the databases created before it existed, whose posts are then
+/// taken as dated in the local timezone.
+/// The ids of the deleted posts are kept in the `deleted_posts` table, so that
+/// they are not given again, and the state of the board in the single row of
+/// the `board_state` table.
+///
+/// Only the last posts that fit in the `History` are read when it is loaded.
+///
+/// # Examples
+///
+/// ```
+/// use oxyboard::core::{History, Post, UserPost};
+/// use oxyboard::storage::StorageBackend;
+/// use oxyboard::storage::sqlite::SqliteStorage;
+///
+/// let dir = std::env::temp_dir().join(format!("oxyboard-sqlite-{}", std::process::id()));
+/// let storage = SqliteStorage::open(dir.to_str().unwrap(), "history.sqlite").unwrap();
+/// for id in 1..=3 {
+///     let request = UserPost::new(String::from(""), String::from("Firefox/48.0.1"), format!("Plop {}", id));
+///     storage.save_post(&Post::new(id, String::from("20161026120000"), request)).unwrap();
+/// }
+///
+/// // Only the last 2 posts fit in this history
+/// let mut history = History::new(&String::from("Oxyboard"), 2);
+/// assert_eq!(storage.load_history(&mut history).unwrap(), 2);
+/// assert_eq!(history.iter().next().unwrap().message(), "Plop 2");
+///
+/// // But the older ones can still be read from the database
+/// assert_eq!(storage.posts_between(1, 2).unwrap().len(), 2);
+/// assert_eq!(storage.posts_of_day("20161026").unwrap().len(), 3);
+///
+/// drop(storage);
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct SqliteStorage {
+	/// Path of the database file.
+	path       : String,
+	/// Connection to the database.
+	connection : Mutex<Connection>,
+}
+
+impl SqliteStorage {
+	/// Opens the `p_file` database in the `p_dir` directory.
+	///
+	/// The directory, the database and its schema are created if needed.
+	pub fn open(p_dir: &str, p_file: &str) -> io::Result<SqliteStorage> {
+		fs::create_dir_all(p_dir)?;
+		let path = format!("{dir}/{file}",
+				dir  = p_dir,
+				file = p_file);
+
+		let connection = Connection::open(&path).map_err(sql_error)?;
+		connection.pragma_update(None, "journal_mode", "WAL").map_err(sql_error)?;
+		connection.execute_batch(SCHEMA).map_err(sql_error)?;
+		add_missing_columns(&connection).map_err(sql_error)?;
+
+		Ok(SqliteStorage {
+			path,
+			connection : Mutex::new(connection),
+		})
+	}
+
+
+	/// Gives the full path of the database file.
+	pub fn file_path(&self) -> &str {
+		&self.path
+	}
+
+
+	/// Runs a query selecting posts.
+	fn select_posts(&self, p_query: &str, p_params: impl rusqlite::Params) -> io::Result<Vec<Post>> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare(p_query).map_err(sql_error)?;
+		let posts = statement.query_map(p_params, row_to_post).map_err(sql_error)?;
+		posts.map(|p| p.map_err(sql_error)).collect()
+	}
+}
+
+
+impl StorageBackend for SqliteStorage {
+	/// Inserts a post in the `posts` table.
+	fn save_post(&self, p_post: &Post) -> io::Result<()> {
+		self.connection.lock().unwrap()
+			.execute("INSERT INTO posts (id, time, login, user_agent, message, instant, second_index) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+					params![p_post.id() as i64, p_post.time(), p_post.login(), p_post.user_agent(), p_post.message(),
+							p_post.instant().to_rfc3339_opts(SecondsFormat::AutoSi, true), p_post.index()])
+			.map(|_| ())
+			.map_err(sql_error)
+	}
+
+
+	/// Inserts the posts in the `posts` table, in a single transaction.
+	fn save_posts(&self, p_posts: &[Post]) -> io::Result<()> {
+		let mut connection = self.connection.lock().unwrap();
+		let transaction = connection.transaction().map_err(sql_error)?;
+		{
+			let mut statement = transaction.prepare_cached("INSERT INTO posts (id, time, login, user_agent, message, instant, second_index) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")
+					.map_err(sql_error)?;
+			for post in p_posts {
+				statement.execute(params![post.id() as i64, post.time(), post.login(), post.user_agent(), post.message(),
+						post.instant().to_rfc3339_opts(SecondsFormat::AutoSi, true), post.index()])
+					.map_err(sql_error)?;
+			}
+		}
+		transaction.commit().map_err(sql_error)
+	}
+
+
+	/// Deletes the row of the post, keeping its id in the same transaction,
+	/// or replaces its message.
+	///
+	/// A failed transaction is rolled back when it is dropped.
+	fn moderate_post(&self, p_post_id: u64, p_moderation: Moderation) -> io::Result<()> {
+		let mut connection = self.connection.lock().unwrap();
+		match p_moderation {
+			Moderation::Delete => {
+				let transaction = connection.transaction().map_err(sql_error)?;
+				transaction.execute("DELETE FROM posts WHERE id = ?1", params![p_post_id as i64]).map_err(sql_error)?;
+				transaction.execute("INSERT OR IGNORE INTO deleted_posts (id) VALUES (?1)", params![p_post_id as i64]).map_err(sql_error)?;
+				transaction.commit()
+			},
+			Moderation::Mask => connection.execute("UPDATE posts SET message = ?2 WHERE id = ?1", params![p_post_id as i64, MASKED_MESSAGE])
+				.map(|_| ()),
+		}
+		.map_err(sql_error)
+	}
+
+
+	/// Rebuilds the database file, giving back the space of the deleted rows.
+	///
+	/// The posts are deleted at once, so there are no records to remove.
+	fn compact(&self) -> io::Result<usize> {
+		self.connection.lock().unwrap().execute_batch("VACUUM").map_err(sql_error)?;
+		Ok(0)
+	}
+
+
+	/// Replaces the row of the `board_state` table.
+	fn save_state(&self, p_state: &BoardState) -> io::Result<()> {
+		self.connection.lock().unwrap()
+				.execute("INSERT OR REPLACE INTO board_state (id, next_post_id, board_name) VALUES (1, ?1, ?2)",
+						params![p_state.next_post_id.min(i64::MAX as u64) as i64, p_state.board_name])
+				.map(|_| ())
+				.map_err(sql_error)
+	}
+
+
+	/// Reads the row of the `board_state` table, if any.
+	fn load_state(&self) -> io::Result<Option<BoardState>> {
+		self.connection.lock().unwrap()
+				.query_row("SELECT next_post_id, board_name FROM board_state WHERE id = 1", [], |r| Ok(BoardState {
+					next_post_id : r.get::<_, i64>(0)? as u64,
+					board_name   : r.get(1)?,
+				}))
+				.optional()
+				.map_err(sql_error)
+	}
+
+
+	/// Loads the last posts of the database into an `History`.
+	///
+	/// Only `p_history.max_size()` posts are read. Returns the number of posts loaded.
+	fn load_history(&self, p_history: &mut History) -> io::Result<usize> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare(
+				"SELECT id, time, login, user_agent, message, instant, second_index FROM (
+					SELECT * FROM posts ORDER BY id DESC LIMIT ?1
+				) ORDER BY id").map_err(sql_error)?;
+
+		let posts = statement.query_map(params![p_history.max_size() as i64], row_to_post).map_err(sql_error)?;
+
+		let mut count = 0;
+		for post in posts {
+			p_history.add_full_post(post.map_err(sql_error)?);
+			count += 1;
+		}
+
+		// The ids of the deleted posts must not be given again
+		let last_deleted: Option<i64> = connection.query_row("SELECT MAX(id) FROM deleted_posts", [], |r| r.get(0)).map_err(sql_error)?;
+		if let Some(id) = last_deleted {
+			p_history.skip_post_ids(id as u64);
+		}
+		Ok(count)
+	}
+
+
+	/// Selects the posts of the id range.
+	///
+	/// SQLite integers are signed, so the ids are capped to `i64::MAX`.
+	fn posts_between(&self, p_first_id: u64, p_last_id: u64) -> io::Result<Vec<Post>> {
+		self.select_posts("SELECT id, time, login, user_agent, message, instant, second_index FROM posts WHERE id BETWEEN ?1 AND ?2 ORDER BY id",
+				params![p_first_id.min(i64::MAX as u64) as i64, p_last_id.min(i64::MAX as u64) as i64])
+	}
+
+
+	/// Selects the posts of the day, using the index on their time.
+	fn posts_of_day(&self, p_day: &str) -> io::Result<Vec<Post>> {
+		self.select_posts("SELECT id, time, login, user_agent, message, instant, second_index FROM posts WHERE time BETWEEN ?1 AND ?2 ORDER BY id",
+				params![format!("{}000000", p_day), format!("{}235959", p_day)])
+	}
+
+
+	/// Selects the posts one id at a time, using the primary key.
+	fn posts_with_ids(&self, p_ids: &[u64]) -> io::Result<Vec<Post>> {
+		let mut ids = p_ids.to_vec();
+		ids.sort_unstable();
+		ids.dedup();
+
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare_cached("SELECT id, time, login, user_agent, message, instant, second_index FROM posts WHERE id = ?1")
+				.map_err(sql_error)?;
+		let mut posts = Vec::new();
+		for id in ids.into_iter().filter(|id| *id <= i64::MAX as u64) {
+			if let Some(post) = statement.query_row(params![id as i64], row_to_post).optional().map_err(sql_error)? {
+				posts.push(post);
+			}
+		}
+		Ok(posts)
+	}
+
+
+	/// Steps through the rows of the `posts` table.
+	fn for_each_post(&self, p_action: &mut dyn FnMut(Post)) -> io::Result<()> {
+		let connection = self.connection.lock().unwrap();
+		let mut statement = connection.prepare("SELECT id, time, login, user_agent, message, instant, second_index FROM posts ORDER BY id")
+				.map_err(sql_error)?;
+		for post in statement.query_map([], row_to_post).map_err(sql_error)? {
+			p_action(post.map_err(sql_error)?);
+		}
+		Ok(())
+	}
+
+
+	/// Gives the path of the database file.
+	fn location(&self) -> String {
+		self.path.clone()
+	}
+}
+
+
+/// Builds a `Post` from a row of the `posts` table.
+///
+/// The columns are expected in the `id, time, login, user_agent, message, instant, second_index` order.
+fn row_to_post(p_row: &rusqlite::Row) -> rusqlite::Result<Post> {
+	let id = p_row.get::<_, i64>(0)? as u64;
+	let user_post = UserPost::new(p_row.get(2)?, p_row.get(3)?, p_row.get(4)?);
+	let instant = p_row.get::<_, Option<String>>(5)?
+			.and_then(|i| DateTime::parse_from_rfc3339(&i).ok());
+
+	let post = match instant {
+		Some(instant) => Post::with_time(id, instant.with_timezone(&Utc), p_row.get(1)?, user_post),
+		None          => Post::new(id, p_row.get(1)?, user_post)
+	};
+	Ok(post.with_index(p_row.get(6)?))
+}
+
+
+/// Adds the `instant` and `second_index` columns to the `posts` table of the
+/// databases created before they existed.
+fn add_missing_columns(p_connection: &Connection) -> rusqlite::Result<()> {
+	let has_instant = p_connection.prepare("SELECT instant FROM posts LIMIT 0").is_ok();
+	if !has_instant {
+		p_connection.execute("ALTER TABLE posts ADD COLUMN instant TEXT", [])?;
+	}
+	let has_index = p_connection.prepare("SELECT second_index FROM posts LIMIT 0").is_ok();
+	if !has_index {
+		p_connection.execute("ALTER TABLE posts ADD COLUMN second_index INTEGER NOT NULL DEFAULT 1", [])?;
+	}
+	Ok(())
+}
+
+
+/// Converts an SQLite error into an I/O error.
+fn sql_error(p_error: rusqlite::Error) -> io::Error {
+	io::Error::other(p_error)
+}
@@ -2,6 +2,9 @@
 // board DOM.
 var g_updating = false;
 
+// The URL prefix of the board's routes (empty for the default board).
+var g_baseUrl = "";
+
 
 function showSuccessFeedback(p_message) {
 	addAlert("success", p_message);
@@ -26,17 +29,42 @@ function closeAlert() {
 	$("#feedback-alert").alert("close");
 }
 
+// Converts a post of the XML backend into a plain object.
+function xmlPostToObject(p_post) {
+	return {
+		id        : p_post.attr("id"),
+		time      : p_post.attr("time"),
+		index     : parseInt(p_post.attr("index") || "1"),
+		user_agent: p_post.find("info").text(),
+		login     : p_post.find("login").text(),
+		message   : p_post.find("message").text()
+	};
+}
+
+// Escapes the HTML special characters of a text.
+function escapeHtml(p_text) {
+	return $("<div>").text(p_text).html().replace(/"/g, "&quot;");
+}
+
+// The message of a post is already normalized by the server, the other
+// fields are escaped.
 function postToHtml(p_post) {
-	var id         = p_post.attr("id"),
-		time       = p_post.attr("time"),
+	// Skip the posts already displayed
+	if ($("#post-" + p_post.id).length > 0)
+		return "";
+
+	var id         = p_post.id,
+		time       = p_post.time,
 		date       = time.substring(6,8) + "/" + time.substring(4,6) + "/" + time.substring(0,4),
-		clock      = time.substring(8,10) + ":" + time.substring(10,12) + ":" + time.substring(12),
-		user_agent = p_post.find("info").text(),
-		login      = p_post.find("login").text(),
+		// The index disambiguates the posts of a same second, like in a norloge
+		clock      = time.substring(8,10) + ":" + time.substring(10,12) + ":" + time.substring(12)
+				+ (p_post.index > 1 ? "^" + p_post.index : ""),
+		user_agent = p_post.user_agent,
+		login      = p_post.login,
 		author     = (login.length == 0 ? user_agent.substring(0, 16) : login),
-		message    = p_post.find("message").text();
+		message    = p_post.message;
 	return "<tr id=\"post-" + id + "\">"
-		+ "<td class=\"post-author\" title=\"" + user_agent + "\">" + author + "</td>"
+		+ "<td class=\"post-author\" title=\"" + escapeHtml(user_agent) + "\">" + escapeHtml(author) + "</td>"
 		+ "<td class=\"post-time\" title=\"" + date + "\">" + clock + "</td>"
 		+ "<td class=\"post-message text-justify\">" + message + "</td>"
 		+ "</tr>";
@@ -51,16 +79,18 @@ function insertAtCaret(p_element, p_text) {
 }
 
 
-function loadBoard() {
+function loadBoard(p_baseUrl) {
+	g_baseUrl = p_baseUrl || "";
+
 	$.ajax({
-		url     : "/backend/last/100",
+		url     : g_baseUrl + "/backend/last/100",
 		dataType: "xml"
 	}).done(function(p_response) {
 		var newPostsHtml = "";
 		$(p_response).find("post")
 			.nextAll()
 			.each(function() {
-				newPostsHtml = postToHtml($(this)) + newPostsHtml;
+				newPostsHtml = postToHtml(xmlPostToObject($(this))) + newPostsHtml;
 			});
 
 		// Fill the board display with the build content
@@ -68,6 +98,9 @@ function loadBoard() {
 			$("#board table").append(newPostsHtml);
 		}
 
+		// Follow the new posts
+		listenBoard();
+
 		// Add a click event handler to the authors
 		$("#board").on("click", ".post-author", function() {
 			console.log("Selected author " + $(this).text());
@@ -90,6 +123,35 @@ function loadBoard() {
 	$("#message").focus();
 }
 
+// Returns the id of the last displayed post, or 0 if there is none.
+function lastPostId() {
+	var lastPost = $("#board tr:last");
+	return lastPost.length > 0 ? lastPost.attr("id").replace("post-", "") : 0;
+}
+
+// Receives the new posts through server-sent events.
+// Returns false if the browser does not support them.
+function listenBoard() {
+	if (typeof(EventSource) === "undefined")
+		return false;
+
+	var source = new EventSource(g_baseUrl + "/events?since=" + lastPostId());
+	source.addEventListener("post", function(p_event) {
+		var postHtml = postToHtml(JSON.parse(p_event.data));
+		if (postHtml.length > 0) {
+			$("#board table").append(postHtml);
+		}
+	});
+	source.addEventListener("delete", function(p_event) {
+		$("#post-" + JSON.parse(p_event.data).id).remove();
+	});
+	source.addEventListener("mask", function(p_event) {
+		var post = JSON.parse(p_event.data);
+		$("#post-" + post.id + " .post-message").html(post.message);
+	});
+	return true;
+}
+
 function updateBoard() {
 	/* Do nothing if there already is an update running */
 	if (g_updating === true)
@@ -98,9 +160,9 @@ function updateBoard() {
 	// Lock updating
 	g_updating = true;
 
-	var lastId = $("#board tr:last").attr("id").replace("post-", "");
+	var lastId = lastPostId();
 	$.ajax({
-		url     : "/backend/since/" + lastId,
+		url     : g_baseUrl + "/backend/since/" + lastId,
 		dataType: "xml"
 	}).done(function(p_response) {
 		var newPostsHtml = "";
@@ -112,12 +174,12 @@ function updateBoard() {
 		var refPost = $(p_response).find("post[id='" + lastId + "']");
 		if (refPost.length > 0) {
 			refPost.prevAll().each(function() {
-				newPostsHtml += postToHtml($(this));
+				newPostsHtml += postToHtml(xmlPostToObject($(this)));
 			});
 		}
 		else {
 			$(p_response).find("post").each(function() {
-				newPostsHtml = postToHtml($(this)) + newPostsHtml;
+				newPostsHtml = postToHtml(xmlPostToObject($(this))) + newPostsHtml;
 			});
 		}
 